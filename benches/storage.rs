@@ -0,0 +1,82 @@
+// Times filling sheets with cells laid out in different ways, and reports how
+// much memory each sheet holds on to. Memory should grow with the number of
+// filled cells, not with how far apart they are.
+//
+// Run with `cargo bench`.
+
+use spreadsheet::engine::Spreadsheet;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+// Keeps a running total of the bytes currently allocated.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn fill(name: &str, cells: &[(usize, usize, String)]) -> Spreadsheet {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut sheet = Spreadsheet::new();
+    for (x, y, contents) in cells {
+        sheet.set(*x, *y, contents.clone());
+    }
+    let elapsed = start.elapsed();
+    let bytes = ALLOCATED.load(Ordering::Relaxed) - before;
+    println!(
+        "{:<28} {:>7} cells {:>10.1?} {:>11} bytes {:>6} bytes/cell",
+        name,
+        cells.len(),
+        elapsed,
+        bytes,
+        bytes / cells.len()
+    );
+    sheet
+}
+
+fn main() {
+    let n = 10_000;
+
+    let dense: Vec<_> = (0..n).map(|i| (i % 100, i / 100, i.to_string())).collect();
+    fill("dense 100x100 block", &dense);
+
+    let diagonal: Vec<_> = (0..n).map(|i| (i * 10, i * 10, i.to_string())).collect();
+    fill("diagonal out to 100000", &diagonal);
+
+    // A simple linear congruential generator, so runs are repeatable.
+    let mut seed: u64 = 1;
+    let mut next = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (seed >> 33) as usize % 1_000_000
+    };
+    let scattered: Vec<_> = (0..n).map(|i| (next(), next(), i.to_string())).collect();
+    fill("scattered over 1000000^2", &scattered);
+
+    let mut chain = vec![(0, 0, "1".to_string())];
+    chain.extend((1..n).map(|y| (0, y, format!("=A{}+1", y))));
+    let mut sheet = fill("formula chain", &chain);
+    let start = Instant::now();
+    sheet.set(0, 0, "2".to_string());
+    println!(
+        "{:<28} {:>7} cells {:>10.1?}",
+        "recalculate chain",
+        n,
+        start.elapsed()
+    );
+    assert_eq!(sheet.show_cell(0, n - 1), (n + 1).to_string());
+}
@@ -1,18 +1,43 @@
-use maplit::*;
 use std::cmp::max;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::io;
 use std::num;
 
 pub struct Spreadsheet {
     // The maximum X and Y values of filled cells in the sheet.
     max_x: usize,
     max_y: usize,
-    // The width and height of the cells array.
-    arr_w: usize,
-    arr_h: usize,
 
-    cells: Vec<Cell>,
+    // Only cells which have contents, or which a formula refers to, are
+    // stored, so memory use doesn't depend on how far apart cells are.
+    cells: HashMap<CellRef, Cell>,
+    // Every range a formula refers to, along with the cell holding the
+    // formula. Ranges aren't recorded in the backrefs of the cells they
+    // cover, since they may cover many more cells than are filled.
+    range_refs: Vec<(CellRange, CellRef)>,
+    // Every reference to another sheet, by sheet name, along with the cell
+    // holding the formula. Single cells are ranges of one cell.
+    sheet_refs: Vec<(String, CellRange, CellRef)>,
+    // The values of the cells on other sheets which formulas here refer to,
+    // by the sheet name the formulas use. The workbook keeps them up to date.
+    imports: HashMap<String, HashMap<CellRef, Value>>,
+    // Every workbook name a formula uses, in lowercase, along with the cell
+    // holding the formula, and the names' definitions, which the workbook
+    // keeps up to date like `imports`.
+    name_refs: Vec<(String, CellRef)>,
+    names: HashMap<String, Expr>,
+    // The edits made to the sheet, for undoing and redoing them.
+    history: History,
+    // Whether recalculating is left to the workbook, which does it across
+    // all its sheets once it has made an edit.
+    deferring: bool,
+}
+
+impl Default for Spreadsheet {
+    fn default() -> Spreadsheet {
+        Spreadsheet::new()
+    }
 }
 
 impl Spreadsheet {
@@ -20,50 +45,68 @@ impl Spreadsheet {
         Spreadsheet {
             max_x: 0,
             max_y: 0,
-            arr_w: 1,
-            arr_h: 1,
-            cells: vec![Cell::empty()],
+            cells: HashMap::new(),
+            range_refs: vec![],
+            sheet_refs: vec![],
+            imports: HashMap::new(),
+            name_refs: vec![],
+            names: HashMap::new(),
+            history: History::default(),
+            deferring: false,
         }
     }
 
     pub fn raw_cell(&self, x: usize, y: usize) -> String {
-        let mut cell = &Cell::empty();
-        if x < self.arr_w && y < self.arr_h {
-            cell = &self.cells[x + y * self.arr_w]
-        };
-
-        cell.contents.clone()
+        self.cell(x, y)
+            .map_or_else(String::new, |cell| cell.contents.clone())
     }
 
     pub fn show_cell(&self, x: usize, y: usize) -> String {
-        let mut cell = &Cell::empty();
-        if x < self.arr_w && y < self.arr_h {
-            cell = &self.cells[x + y * self.arr_w]
-        };
+        match self.cell(x, y) {
+            None => String::new(),
+            Some(cell) if cell.contents.starts_with('=') => cell.value.to_string(),
+            // An apostrophe only marks the contents as text; it isn't shown.
+            Some(cell) => cell
+                .contents
+                .strip_prefix('\'')
+                .unwrap_or(&cell.contents)
+                .to_string(),
+        }
+    }
 
-        match cell.contents.strip_prefix("=") {
-            None => cell.contents.clone(),
-            Some(rest) => {
-                let e = Expr::parse(rest);
-                match e {
-                    Ok(expr) => expr
-                        .eval(&self)
-                        .map_or_else(|e| e.to_string(), |v| v.to_string()),
-                    Err(errstr) => errstr.to_string(),
-                }
-            }
+    // The full error behind a cell showing an error code, if there is one.
+    pub fn cell_error(&self, x: usize, y: usize) -> Option<&Error> {
+        match self.cell(x, y) {
+            Some(Cell {
+                value: Value::Error(e),
+                ..
+            }) => Some(e),
+            _ => None,
         }
     }
 
-    fn get_expr(&self, x: usize, y: usize) -> Result<Expr, Error> {
-        let mut cell = &Cell::empty();
-        if x < self.arr_w && y < self.arr_h {
-            cell = &self.cells[x + y * self.arr_w]
-        };
+    fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
+        self.cells.get(&CellRef(x, y))
+    }
 
-        match cell.contents.strip_prefix("=") {
-            None => Ok(Expr::String(cell.contents.clone())),
-            Some(rest) => Expr::parse(rest),
+    fn cell_mut(&mut self, x: usize, y: usize) -> &mut Cell {
+        self.cells.entry(CellRef(x, y)).or_insert_with(Cell::empty)
+    }
+
+    // Drops a cell from storage once it's empty and nothing refers to it.
+    fn forget_if_unused(&mut self, cell: CellRef) {
+        if let Some(c) = self.cells.get(&cell) {
+            if c.contents.is_empty() && c.backrefs.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    // Returns the cached value of a cell.
+    fn cell_value(&self, x: usize, y: usize) -> Value {
+        match self.cell(x, y) {
+            Some(cell) => cell.value.clone(),
+            None => Value::Blank,
         }
     }
 
@@ -71,41 +114,326 @@ impl Spreadsheet {
         (self.max_x, self.max_y)
     }
 
+    // Setting a cell to "" clears it.
     pub fn set(&mut self, x: usize, y: usize, contents: String) {
-        self.max_x = max(x, self.max_x);
-        self.max_y = max(y, self.max_y);
+        let before = self.raw_cell(x, y);
+        if before != contents {
+            self.record(vec![Command::Set(CellRef(x, y), before, contents.clone())]);
+        }
+        self.put(x, y, contents);
+    }
 
-        if x >= self.arr_w || y >= self.arr_h {
-            self.grow_array_to_fit(x, y)
+    // Like `set`, but without recording the change in the history.
+    fn put(&mut self, x: usize, y: usize, contents: String) {
+        if contents.is_empty() {
+            self.store(x, y, contents);
+            if x == self.max_x || y == self.max_y {
+                self.shrink_extent();
+            }
+        } else {
+            self.max_x = max(x, self.max_x);
+            self.max_y = max(y, self.max_y);
+            self.store(x, y, contents);
         }
+    }
 
-        let new_cell = &mut self.cells[x + y * self.arr_w];
-        new_cell.contents = contents;
+    pub fn clear(&mut self, x: usize, y: usize) {
+        self.set(x, y, String::new());
     }
 
-    fn grow_array_to_fit(&mut self, x: usize, y: usize) {
-        let mut new_arr_w = self.arr_w;
-        let mut new_arr_h = self.arr_h;
-        while new_arr_w <= x {
-            new_arr_w *= 2;
+    // Clears every cell in the rectangle with the given corners, as a single
+    // step in the history.
+    pub fn clear_range(&mut self, from: (usize, usize), to: (usize, usize)) {
+        let range = CellRange(
+            CellRef(from.0.min(to.0), from.1.min(to.1)),
+            CellRef(from.0.max(to.0), from.1.max(to.1)),
+        );
+        let filled: Vec<CellRef> = self
+            .cells
+            .iter()
+            .filter(|(r, c)| range.contains(**r) && !c.contents.is_empty())
+            .map(|(r, _)| *r)
+            .collect();
+        self.record(
+            filled
+                .iter()
+                .map(|r| Command::Set(*r, self.raw_cell(r.0, r.1), String::new()))
+                .collect(),
+        );
+        for r in filled {
+            self.store(r.0, r.1, String::new());
         }
+        self.shrink_extent();
+    }
+
+    // Recomputes the maximum X and Y values from the cells with contents.
+    fn shrink_extent(&mut self) {
+        let filled = self
+            .cells
+            .iter()
+            .filter(|(_, c)| !c.contents.is_empty())
+            .map(|(r, _)| *r);
+        self.max_x = filled.clone().map(|r| r.0).max().unwrap_or(0);
+        self.max_y = filled.map(|r| r.1).max().unwrap_or(0);
+    }
+
+    // Sets the contents of a cell and updates everything that depends on it,
+    // without touching the sheet's extent.
+    fn store(&mut self, x: usize, y: usize, contents: String) {
+        self.link(x, y, contents);
+        self.recalculate(&[CellRef(x, y)]);
+        self.forget_if_unused(CellRef(x, y));
+    }
+
+    // Sets the contents of a cell and records what it refers to, leaving its
+    // value and those of its dependents to be recalculated.
+    fn link(&mut self, x: usize, y: usize, contents: String) {
+        let expr = match contents.strip_prefix('=') {
+            None => Ok(input::classify(&contents)),
+            // Count columns from the start of the cell, "=" included.
+            Some(rest) => Expr::parse(rest).map_err(|e| match e {
+                Error::ParseError(col, msg) => Error::ParseError(col + 1, msg),
+                e => e,
+            }),
+        };
+        let refs = expr.as_ref().map_or_else(|_| vec![], |e| e.cell_refs());
+        let ranges = expr.as_ref().map_or_else(|_| vec![], |e| e.cell_ranges());
+        let sheet_refs = expr.as_ref().map_or_else(|_| vec![], |e| e.sheet_refs());
+        let names = expr.as_ref().map_or_else(|_| vec![], |e| e.names());
 
-        while new_arr_h <= y {
-            new_arr_h *= 2;
+        let here = CellRef(x, y);
+        self.unlink(here);
+        for r in &refs {
+            self.cell_mut(r.0, r.1).backrefs.push(here);
         }
-        let mut new_cells = Vec::with_capacity(new_arr_w * new_arr_h);
-        for yy in 0..self.arr_h {
-            for xx in 0..self.arr_w {
-                new_cells.push(self.cells[xx + yy * self.arr_w].clone());
+        self.range_refs.extend(ranges.iter().map(|r| (*r, here)));
+        self.sheet_refs
+            .extend(sheet_refs.iter().map(|(name, r)| (name.clone(), *r, here)));
+        self.name_refs
+            .extend(names.iter().map(|name| (name.clone(), here)));
+        let cell = self.cell_mut(x, y);
+        cell.contents = contents;
+        cell.expr = expr;
+        cell.refs = refs;
+        cell.ranges = ranges;
+        cell.sheet_refs = sheet_refs;
+        cell.names = names;
+    }
+
+    pub fn insert_rows(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Insert(Axis::Row, at, n));
+    }
+
+    pub fn delete_rows(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Delete(Axis::Row, at, n));
+    }
+
+    pub fn insert_cols(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Insert(Axis::Col, at, n));
+    }
+
+    pub fn delete_cols(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Delete(Axis::Col, at, n));
+    }
+
+    // Moves every cell as `edit` says, rewriting formulas to follow the cells
+    // they refer to, and then rebuilds the sheet. References to deleted cells
+    // become #REF! errors.
+    fn restructure(&mut self, edit: Edit) {
+        // Undoing the edit moves the cells back, but can't bring back the
+        // contents of deleted cells or of formulas changed other than by
+        // moving, so those are kept.
+        let changed = self
+            .cells
+            .iter()
+            .filter(|(_, c)| !c.contents.is_empty())
+            .filter(|(r, c)| edit.apply(**r).is_none() || adjusted(c, edit) != c.contents)
+            .map(|(r, c)| (*r, c.contents.clone()))
+            .collect();
+        self.record(vec![Command::Restructure(edit, changed)]);
+        self.move_cells(edit);
+    }
+
+    // Like `restructure`, but without recording the change in the history.
+    fn move_cells(&mut self, edit: Edit) {
+        let old = std::mem::take(&mut self.cells);
+        self.range_refs.clear();
+        self.sheet_refs.clear();
+        self.name_refs.clear();
+        self.max_x = 0;
+        self.max_y = 0;
+
+        let mut moved = vec![];
+        for (r, cell) in old {
+            if let Some(to) = edit.apply(r) {
+                moved.push((to, adjusted(&cell, edit)));
             }
-            for _ in self.arr_w..new_arr_w {
-                new_cells.push(Cell::empty());
+        }
+        self.fill(moved);
+    }
+
+    // Sets the contents of many cells at once, recalculating each affected
+    // cell once at the end rather than after every change.
+    fn fill(&mut self, cells: Vec<(CellRef, String)>) {
+        let mut changed = vec![];
+        for (r, contents) in cells {
+            if contents.is_empty() {
+                continue;
             }
+            self.max_x = max(r.0, self.max_x);
+            self.max_y = max(r.1, self.max_y);
+            self.link(r.0, r.1, contents);
+            changed.push(r);
         }
-        new_cells.resize(new_arr_w * new_arr_h, Cell::empty());
-        self.cells = new_cells;
-        self.arr_w = new_arr_w;
-        self.arr_h = new_arr_h;
+        self.recalculate(&changed);
+    }
+
+    // Sets the contents of formulas the workbook has rewritten, as a single
+    // step in the history, leaving them to be recalculated.
+    fn relink(&mut self, cells: Vec<(CellRef, String)>) {
+        self.record(
+            cells
+                .iter()
+                .map(|(r, contents)| Command::Set(*r, self.raw_cell(r.0, r.1), contents.clone()))
+                .collect(),
+        );
+        for (r, contents) in cells {
+            self.link(r.0, r.1, contents);
+        }
+    }
+
+    // Copies the contents of `src` into `dst`. Relative references in a
+    // formula move by the distance between the two cells.
+    pub fn copy_cell(&mut self, src: (usize, usize), dst: (usize, usize)) -> Result<(), Error> {
+        let contents = self.raw_cell(src.0, src.1);
+        let contents = match (contents.starts_with('='), self.cell(src.0, src.1)) {
+            (true, Some(Cell { expr: Ok(e), .. })) => {
+                let dx = dst.0 as isize - src.0 as isize;
+                let dy = dst.1 as isize - src.1 as isize;
+                format!("={}", e.shift(dx, dy)?)
+            }
+            _ => contents,
+        };
+        self.set(dst.0, dst.1, contents);
+        Ok(())
+    }
+
+    // Removes `cell` from the backrefs of every cell it currently refers to,
+    // and forgets the ranges, other sheets' cells and names it refers to.
+    fn unlink(&mut self, cell: CellRef) {
+        let (refs, has_ranges, has_sheet_refs, has_names) = match self.cell(cell.0, cell.1) {
+            Some(c) => (
+                c.refs.clone(),
+                !c.ranges.is_empty(),
+                !c.sheet_refs.is_empty(),
+                !c.names.is_empty(),
+            ),
+            None => return,
+        };
+        for r in refs {
+            self.cell_mut(r.0, r.1).backrefs.retain(|b| *b != cell);
+            self.forget_if_unused(r);
+        }
+        if has_ranges {
+            self.range_refs.retain(|(_, c)| *c != cell);
+        }
+        if has_sheet_refs {
+            self.sheet_refs.retain(|(_, _, c)| *c != cell);
+        }
+        if has_names {
+            self.name_refs.retain(|(_, c)| *c != cell);
+        }
+    }
+
+    // The cells whose formulas refer to `cell`, once for each reference or
+    // range that covers it.
+    fn referrers(&self, cell: CellRef) -> Vec<CellRef> {
+        let mut out = self
+            .cell(cell.0, cell.1)
+            .map_or_else(Vec::new, |c| c.backrefs.clone());
+        out.extend(
+            self.range_refs
+                .iter()
+                .filter(|(r, _)| r.contains(cell))
+                .map(|(_, c)| *c),
+        );
+        out
+    }
+
+    // Recomputes the cached values of `changed` and every cell that depends
+    // on them, in topological order. Cells which never become ready are part of
+    // (or downstream of) a reference cycle.
+    fn recalculate(&mut self, changed: &[CellRef]) {
+        if self.deferring {
+            return;
+        }
+        let cells = self.dependents(changed);
+        let members: HashSet<CellRef> = cells.iter().copied().collect();
+        let mut pending: HashMap<CellRef, usize> = HashMap::new();
+        for cell in &cells {
+            // This counts each reference to a member, matching `referrers`.
+            let n = self.cell(cell.0, cell.1).map_or(0, |c| {
+                let in_ranges: usize = c
+                    .ranges
+                    .iter()
+                    .map(|r| cells.iter().filter(|m| r.contains(**m)).count())
+                    .sum();
+                c.refs.iter().filter(|r| members.contains(r)).count() + in_ranges
+            });
+            pending.insert(*cell, n);
+        }
+
+        let mut ready: Vec<CellRef> = cells.iter().filter(|c| pending[c] == 0).copied().collect();
+        while let Some(cell) = ready.pop() {
+            pending.remove(&cell);
+            self.evaluate(cell);
+
+            for b in self.referrers(cell) {
+                if let Some(n) = pending.get_mut(&b) {
+                    *n -= 1;
+                    if *n == 0 {
+                        ready.push(b);
+                    }
+                }
+            }
+        }
+
+        for cell in pending.keys() {
+            self.cell_mut(cell.0, cell.1).value = Value::Error(Error::CircularReference);
+        }
+    }
+
+    // Recomputes the cached value of one cell from those it refers to.
+    fn evaluate(&mut self, cell: CellRef) {
+        let expr = self.cell_mut(cell.0, cell.1).expr.clone();
+        let value = expr.and_then(|e| e.eval(self)).unwrap_or_else(Value::Error);
+        self.cell_mut(cell.0, cell.1).value = value;
+    }
+
+    // Returns the cells in `start` and every cell that transitively refers to
+    // them.
+    fn dependents(&self, start: &[CellRef]) -> Vec<CellRef> {
+        let mut seen: HashSet<CellRef> = start.iter().copied().collect();
+        let mut stack: Vec<CellRef> = seen.iter().copied().collect();
+        let mut out = vec![];
+        while let Some(cell) = stack.pop() {
+            out.push(cell);
+            for b in self.referrers(cell) {
+                if seen.insert(b) {
+                    stack.push(b);
+                }
+            }
+        }
+        out
+    }
+}
+
+// The contents of `cell` once it has moved as `edit` says, with the formula
+// rewritten to follow the cells it refers to.
+fn adjusted(cell: &Cell, edit: Edit) -> String {
+    match (&cell.expr, cell.contents.starts_with('=')) {
+        (Ok(e), true) => format!("={}", e.adjust(edit)),
+        _ => cell.contents.clone(),
     }
 }
 
@@ -121,91 +449,1179 @@ mod spreadsheet_tests {
         assert_eq!(sheet.show_cell(0, 0), "hi");
         assert_eq!(sheet.show_cell(1, 1), "hello");
     }
+
+    #[test]
+    fn test_self_reference() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=Ref(0,0)".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "#CIRC!");
+    }
+
+    #[test]
+    fn test_reference_cycle() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=Ref(1,0)".to_string());
+        sheet.set(1, 0, "=Ref(2,0)".to_string());
+        sheet.set(2, 0, "=Ref(0,0)".to_string());
+        sheet.set(0, 1, "=(Ref(0,0)+1)".to_string());
+        for x in 0..3 {
+            assert_eq!(sheet.show_cell(x, 0), "#CIRC!");
+        }
+        assert_eq!(sheet.show_cell(0, 1), "#CIRC!");
+        assert_eq!(sheet.cell_error(0, 1), Some(&Error::CircularReference));
+
+        sheet.set(2, 0, "=5".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "5");
+        assert_eq!(sheet.show_cell(0, 1), "6");
+    }
+
+    #[test]
+    fn test_recalculates_dependents() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=1".to_string());
+        sheet.set(1, 0, "=(Ref(0,0)+1)".to_string());
+        sheet.set(2, 0, "=(Ref(0,0)+Ref(1,0))".to_string());
+        sheet.set(3, 0, "=(Ref(2,0)+Ref(1,0))".to_string());
+        assert_eq!(sheet.show_cell(3, 0), "5");
+
+        sheet.set(0, 0, "=10".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "11");
+        assert_eq!(sheet.show_cell(2, 0), "21");
+        assert_eq!(sheet.show_cell(3, 0), "32");
+    }
+
+    #[test]
+    fn test_reference_to_unset_cell() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=(Ref(1,1)=5)".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "false");
+
+        sheet.set(1, 1, "=5".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "true");
+    }
+
+    #[test]
+    fn test_range_values() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=1".to_string());
+        sheet.set(1, 0, "=2".to_string());
+        sheet.set(0, 1, "=3".to_string());
+        sheet.set(1, 1, "=4".to_string());
+        sheet.set(2, 0, "=A1:B2".to_string());
+        sheet.set(2, 1, "=(A1:B2+10)".to_string());
+        sheet.set(2, 2, "=(A1:B1=1)".to_string());
+        sheet.set(2, 3, "=if((A1:B2=2),0,A1:B2)".to_string());
+        assert_eq!(sheet.show_cell(2, 0), "{1,2;3,4}");
+        assert_eq!(sheet.show_cell(2, 1), "{11,12;13,14}");
+        assert_eq!(sheet.show_cell(2, 3), "{1,0;3,4}");
+        assert_eq!(sheet.show_cell(2, 2), "{true,false}");
+    }
+
+    #[test]
+    fn test_range_errors() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=1".to_string());
+        sheet.set(0, 1, "=(1=false)".to_string());
+        sheet.set(1, 0, "=SUM(A1:A2)".to_string());
+        sheet.set(1, 1, "=(A1:A2+B1:C1)".to_string());
+        sheet.set(1, 2, "=A1:A2/0".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "#VALUE!");
+        assert_eq!(sheet.show_cell(1, 2), "{#DIV/0!;#VALUE!}");
+
+        sheet.set(0, 1, "=2".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "3");
+        assert_eq!(sheet.show_cell(1, 1), "#VALUE!");
+        assert!(sheet
+            .cell_error(1, 1)
+            .unwrap()
+            .to_string()
+            .starts_with("can't combine"));
+    }
+
+    #[test]
+    fn test_copy_cell() -> Result<(), Error> {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=1".to_string());
+        sheet.set(1, 0, "=2".to_string());
+        sheet.set(0, 1, "=3".to_string());
+        sheet.set(2, 2, "=(A1+($A$1+SUM(A$1:B1)))".to_string());
+        sheet.copy_cell((2, 2), (3, 3))?;
+        assert_eq!(sheet.raw_cell(3, 3), "=B2+($A$1+SUM(B$1:C2))");
+
+        sheet.copy_cell((0, 0), (0, 5))?;
+        assert_eq!(sheet.raw_cell(0, 5), "=1");
+        assert!(sheet.copy_cell((2, 2), (1, 2)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error_in_cell() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=(1 + )".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "#ERROR!");
+        assert!(sheet
+            .cell_error(0, 0)
+            .unwrap()
+            .to_string()
+            .starts_with("parse error at column 7:"));
+    }
+
+    #[test]
+    fn test_error_propagation() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=1/0".to_string());
+        sheet.set(0, 1, "=A1+1".to_string());
+        sheet.set(0, 2, "=NOPE()&A2".to_string());
+        sheet.set(0, 3, "=IFERROR(A2,-1)".to_string());
+        sheet.set(0, 4, "=#REF!".to_string());
+        assert_eq!(sheet.show_cell(0, 1), "#DIV/0!");
+        assert_eq!(sheet.show_cell(0, 2), "#NAME?");
+        assert_eq!(sheet.show_cell(0, 3), "-1");
+        assert_eq!(sheet.show_cell(0, 4), "#REF!");
+
+        sheet.set(0, 0, "=2".to_string());
+        assert_eq!(sheet.show_cell(0, 1), "3");
+        assert_eq!(sheet.show_cell(0, 3), "3");
+    }
+
+    #[test]
+    fn test_blank_cells() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=Z9+1".to_string());
+        sheet.set(0, 1, "=\"a\"&Z9&\"b\"".to_string());
+        sheet.set(0, 2, "=Z9".to_string());
+        sheet.set(0, 3, "=and(Z9=0,Z9=\"\",Z9=false,-Z9=0)".to_string());
+        sheet.set(0, 4, "=if(Z9,1,2)".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "1");
+        assert_eq!(sheet.show_cell(0, 1), "\"ab\"");
+        assert_eq!(sheet.show_cell(0, 2), "");
+        assert_eq!(sheet.show_cell(0, 3), "true");
+        assert_eq!(sheet.show_cell(0, 4), "2");
+
+        sheet.set(25, 8, "4".to_string());
+        sheet.set(25, 8, "".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "1");
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "1".to_string());
+        sheet.set(3, 1, "2".to_string());
+        sheet.set(1, 4, "=A1+D2".to_string());
+        assert_eq!(sheet.get_max_dims(), (3, 4));
+
+        sheet.clear(3, 1);
+        assert_eq!(sheet.raw_cell(3, 1), "");
+        assert_eq!(sheet.show_cell(1, 4), "1");
+        assert_eq!(sheet.get_max_dims(), (1, 4));
+
+        sheet.clear(1, 4);
+        assert_eq!(sheet.get_max_dims(), (0, 0));
+        assert_eq!(sheet.cells.len(), 1);
+        sheet.clear(0, 0);
+        assert!(sheet.cells.is_empty());
+    }
+
+    #[test]
+    fn test_clear_range() {
+        let mut sheet = Spreadsheet::new();
+        for y in 0..4 {
+            for x in 0..4 {
+                sheet.set(x, y, (x + y).to_string());
+            }
+        }
+        sheet.set(5, 0, "=SUM(A1:D4)".to_string());
+        sheet.clear_range((3, 3), (1, 0));
+        assert_eq!(sheet.show_cell(5, 0), "6");
+        assert_eq!(sheet.raw_cell(1, 2), "");
+        assert_eq!(sheet.raw_cell(0, 2), "2");
+        assert_eq!(sheet.get_max_dims(), (5, 3));
+
+        sheet.clear_range((0, 0), (5, 0));
+        assert_eq!(sheet.get_max_dims(), (0, 3));
+    }
+
+    #[test]
+    fn test_insert_rows() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "1".to_string());
+        sheet.set(0, 1, "2".to_string());
+        sheet.set(0, 2, "3".to_string());
+        sheet.set(1, 0, "=SUM(A1:A3)+$A$3".to_string());
+        sheet.set(1, 2, "=A1+A2".to_string());
+        sheet.insert_rows(1, 2);
+        assert_eq!(sheet.raw_cell(0, 3), "2");
+        assert_eq!(sheet.raw_cell(1, 0), "=SUM(A1:A5)+$A$5");
+        assert_eq!(sheet.raw_cell(1, 4), "=A1+A4");
+        assert_eq!(sheet.get_max_dims(), (1, 4));
+
+        sheet.set(0, 1, "10".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "19");
+    }
+
+    #[test]
+    fn test_delete_rows() {
+        let mut sheet = Spreadsheet::new();
+        for y in 0..5 {
+            sheet.set(0, y, (y + 1).to_string());
+        }
+        sheet.set(1, 0, "=SUM(A1:A5)".to_string());
+        sheet.set(1, 1, "=A3*2".to_string());
+        sheet.set(1, 4, "=A5+SUM(A2:A3)".to_string());
+        sheet.delete_rows(1, 2);
+        assert_eq!(sheet.raw_cell(0, 1), "4");
+        assert_eq!(sheet.raw_cell(1, 0), "=SUM(A1:A3)");
+        assert_eq!(sheet.show_cell(1, 0), "10");
+        assert_eq!(sheet.raw_cell(1, 2), "=A3+SUM(#REF!)");
+        assert_eq!(sheet.show_cell(1, 2), "#REF!");
+        assert_eq!(sheet.raw_cell(1, 1), "");
+        assert_eq!(sheet.get_max_dims(), (1, 2));
+    }
+
+    #[test]
+    fn test_insert_and_delete_cols() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "1".to_string());
+        sheet.set(1, 0, "2".to_string());
+        sheet.set(2, 0, "3".to_string());
+        sheet.set(0, 1, "=SUM(A1:C1)*C1".to_string());
+        sheet.insert_cols(0, 1);
+        assert_eq!(sheet.raw_cell(1, 1), "=SUM(B1:D1)*D1");
+        assert_eq!(sheet.show_cell(1, 1), "18");
+
+        sheet.delete_cols(2, 2);
+        assert_eq!(sheet.raw_cell(1, 1), "=SUM(B1:B1)*#REF!");
+        assert_eq!(sheet.show_cell(1, 1), "#REF!");
+        sheet.delete_cols(1, 1);
+        assert_eq!(sheet.raw_cell(0, 0), "");
+        assert_eq!(sheet.get_max_dims(), (0, 0));
+    }
+
+    #[test]
+    fn test_typed_input() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "5".to_string());
+        sheet.set(0, 1, "2.5".to_string());
+        sheet.set(0, 2, "10%".to_string());
+        sheet.set(0, 3, "TRUE".to_string());
+        sheet.set(0, 4, "'7".to_string());
+        sheet.set(1, 0, "=A1+A2*2".to_string());
+        sheet.set(1, 1, "=A1*A3".to_string());
+        sheet.set(1, 2, "=if(A4,1,2)".to_string());
+        sheet.set(1, 3, "=A5&\"!\"".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "10");
+        assert_eq!(sheet.show_cell(1, 1), "0.5");
+        assert_eq!(sheet.show_cell(1, 2), "1");
+        assert_eq!(sheet.show_cell(1, 3), "\"7!\"");
+        assert_eq!(sheet.show_cell(0, 2), "10%");
+        assert_eq!(sheet.show_cell(0, 4), "7");
+        assert_eq!(sheet.raw_cell(0, 4), "'7");
+    }
+
+    #[test]
+    fn test_backrefs() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=(Ref(1,1)+Ref(1,1))".to_string());
+        assert_eq!(sheet.cell(1, 1).unwrap().backrefs, vec![CellRef(0, 0)]);
+
+        // An empty cell which nothing refers to any more isn't kept.
+        sheet.set(0, 0, "=Ref(2,2)".to_string());
+        assert!(sheet.cell(1, 1).is_none());
+        assert_eq!(sheet.cell(2, 2).unwrap().backrefs, vec![CellRef(0, 0)]);
+    }
+
+    #[test]
+    fn test_range_dependencies() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=SUM(B1:B100000)".to_string());
+        sheet.set(2, 0, "=A1+B1".to_string());
+        assert_eq!(sheet.cells.len(), 3);
+
+        sheet.set(1, 99999, "5".to_string());
+        sheet.set(1, 0, "2".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "7");
+        assert_eq!(sheet.show_cell(2, 0), "9");
+
+        sheet.set(1, 1, "=SUM(B1:B100000)".to_string());
+        assert_eq!(sheet.show_cell(1, 1), "#CIRC!");
+        assert_eq!(sheet.show_cell(0, 0), "#CIRC!");
+        assert_eq!(sheet.range_refs.len(), 2);
+
+        sheet.set(1, 1, "".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "7");
+        assert_eq!(sheet.range_refs.len(), 1);
+    }
+
+    #[test]
+    fn test_far_away_cells() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(100000, 100000, "x".to_string());
+        sheet.set(0, 0, "=Ref(100000,100000)&\"y\"".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "\"xy\"");
+        assert_eq!(sheet.get_max_dims(), (100000, 100000));
+        assert_eq!(sheet.cells.len(), 2);
+    }
+
+    #[test]
+    fn test_huge_ranges() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(3, 500000, "4".to_string());
+        sheet.set(0, 0, "=SUM(A2:Z1000000)".to_string());
+        sheet.set(1, 0, "=COUNT(A2:XFD1048576)+MAX(B2:XFD1048576)".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "4");
+        assert_eq!(sheet.show_cell(1, 0), "5");
+        // Anything else needs every cell's value, which is too many.
+        sheet.set(2, 0, "=ISBLANK(A2:Z1000000)".to_string());
+        assert_eq!(sheet.show_cell(2, 0), "#NUM!");
+    }
 }
 
 #[derive(Clone)]
 struct Cell {
     contents: String,
+    // The cells this cell's formula refers to, and the ranges.
+    refs: Vec<CellRef>,
+    ranges: Vec<CellRange>,
+    // The cells and ranges it refers to on other sheets, by sheet name, and
+    // the workbook names it uses.
+    sheet_refs: Vec<(String, CellRange)>,
+    names: Vec<String>,
+    // The cells whose formulas refer to this cell.
     backrefs: Vec<CellRef>,
+    // The parsed contents and the last value they evaluated to.
+    expr: Result<Expr, Error>,
+    value: Value,
 }
 
 impl Cell {
     pub fn empty() -> Cell {
         Cell {
             contents: "".to_string(),
+            refs: vec![],
+            ranges: vec![],
+            sheet_refs: vec![],
+            names: vec![],
             backrefs: vec![],
+            expr: Ok(Expr::Blank),
+            value: Value::Blank,
         }
     }
 }
 
-// TODO: Implement cross-cell expression references.
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 struct CellRef(usize, usize);
 
+// The top-left and bottom-right corners of a rectangle of cells.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct CellRange(CellRef, CellRef);
+
+impl CellRange {
+    fn contains(&self, cell: CellRef) -> bool {
+        (self.0 .0..=self.1 .0).contains(&cell.0) && (self.0 .1..=self.1 .1).contains(&cell.1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Axis {
+    Row,
+    Col,
+}
+
+// Inserting or deleting `n` rows or columns, starting at index `at`.
+#[derive(Clone, Copy, Debug)]
+enum Edit {
+    Insert(Axis, usize, usize),
+    Delete(Axis, usize, usize),
+}
+
+impl Edit {
+    fn axis(&self) -> Axis {
+        match *self {
+            Edit::Insert(axis, ..) | Edit::Delete(axis, ..) => axis,
+        }
+    }
+
+    // Where a row or column index ends up, or None if it's deleted.
+    fn index(&self, i: usize) -> Option<usize> {
+        match *self {
+            Edit::Insert(_, at, n) if i >= at => Some(i + n),
+            Edit::Delete(_, at, n) if i >= at + n => Some(i - n),
+            Edit::Delete(_, at, _) if i >= at => None,
+            _ => Some(i),
+        }
+    }
+
+    // Where the ends of the span of indexes `start..=end` end up. The span
+    // grows when rows are inserted inside it and shrinks when some of it is
+    // deleted, and is None if all of it is deleted.
+    fn span(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        match *self {
+            Edit::Insert(..) => Some((self.index(start)?, self.index(end)?)),
+            Edit::Delete(_, at, n) => {
+                if start >= at && end < at + n {
+                    return None;
+                }
+                let start = self.index(start).unwrap_or(at);
+                let end = self.index(end).unwrap_or_else(|| at - 1);
+                Some((start, end))
+            }
+        }
+    }
+
+    // The edit which puts back the rows or columns this one moves.
+    fn inverse(&self) -> Edit {
+        match *self {
+            Edit::Insert(axis, at, n) => Edit::Delete(axis, at, n),
+            Edit::Delete(axis, at, n) => Edit::Insert(axis, at, n),
+        }
+    }
+
+    fn apply(&self, cell: CellRef) -> Option<CellRef> {
+        match self.axis() {
+            Axis::Col => Some(CellRef(self.index(cell.0)?, cell.1)),
+            Axis::Row => Some(CellRef(cell.0, self.index(cell.1)?)),
+        }
+    }
+}
+
+/***** A1-style addresses. *****/
+
+// Columns are named A..Z, then AA..AZ, BA..BZ, and so on.
+pub fn column_name(x: usize) -> String {
+    let mut name = vec![];
+    let mut n = x + 1;
+    while n > 0 {
+        name.push(b'A' + ((n - 1) % 26) as u8);
+        n = (n - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+pub fn parse_column(s: &str) -> Option<usize> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut n: usize = 0;
+    for c in s.bytes() {
+        if !c.is_ascii_alphabetic() {
+            return None;
+        }
+        let digit = (c.to_ascii_uppercase() - b'A') as usize + 1;
+        n = n.checked_mul(26)?.checked_add(digit)?;
+    }
+    Some(n - 1)
+}
+
+// Parses an address like "B12" into zero-based (x, y) coordinates.
+pub fn parse_a1(s: &str) -> Option<(usize, usize)> {
+    let split = s.find(|c: char| !c.is_ascii_alphabetic())?;
+    let (col, row) = s.split_at(split);
+    if !row.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let row = row.parse::<usize>().ok()?.checked_sub(1)?;
+    Some((parse_column(col)?, row))
+}
+
+// Parses an address which may mark its column and row absolute with a "$",
+// like "$A$1" or "B$3".
+fn parse_reference(s: &str) -> Option<Reference> {
+    let (abs_x, s) = match s.strip_prefix('$') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let split = s.find(|c: char| !c.is_ascii_alphabetic())?;
+    let (col, row) = s.split_at(split);
+    let (abs_y, row) = match row.strip_prefix('$') {
+        Some(row) => (true, row),
+        None => (false, row),
+    };
+    let (x, y) = parse_a1(&format!("{}{}", col, row))?;
+    Some(Reference { x, y, abs_x, abs_y })
+}
+
+// Whether `s` is shaped like a cell address, as letters and then digits with
+// optional dollar signs, even if it's not a valid one. Such words can't be
+// names.
+fn is_address_like(s: &str) -> bool {
+    let s = s.strip_prefix('$').unwrap_or(s);
+    let letters = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let row = s[letters..].strip_prefix('$').unwrap_or(&s[letters..]);
+    letters > 0 && !row.is_empty() && row.bytes().all(|c| c.is_ascii_digit())
+}
+
+pub fn a1_name(x: usize, y: usize) -> String {
+    format!("{}{}", column_name(x), y + 1)
+}
+
+#[cfg(test)]
+mod address_tests {
+    use super::*;
+
+    #[test]
+    fn test_column_name() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_name(701), "ZZ");
+        assert_eq!(column_name(702), "AAA");
+    }
+
+    #[test]
+    fn test_parse_column() {
+        for x in [0, 1, 25, 26, 51, 52, 701, 702, 18277] {
+            assert_eq!(parse_column(&column_name(x)), Some(x));
+        }
+        assert_eq!(parse_column("ab"), Some(27));
+        assert_eq!(parse_column(""), None);
+        assert_eq!(parse_column("A1"), None);
+    }
+
+    #[test]
+    fn test_parse_a1() {
+        assert_eq!(parse_a1("A1"), Some((0, 0)));
+        assert_eq!(parse_a1("B12"), Some((1, 11)));
+        assert_eq!(parse_a1("AA3"), Some((26, 2)));
+        assert_eq!(parse_a1("A0"), None);
+        assert_eq!(parse_a1("12"), None);
+        assert_eq!(parse_a1("A"), None);
+        assert_eq!(parse_a1("A1B"), None);
+    }
+}
+
 /***** Parsing, Expressions, Evaluation, Values. *****/
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone)]
 enum Expr {
+    // The contents of an empty cell. Formulas can't contain it.
+    Blank,
     Int(i64),
+    Float(f64),
     Bool(bool),
     String(String),
+    Error(Error),
+
+    Neg(Box<Expr>),
+    BinOp(Op, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    FnCall(String, Vec<Expr>),
+    CellRef(Reference),
+    // The top-left and bottom-right corners of a rectangle of cells.
+    Range(Reference, Reference),
+    // A cell reference or range on the named sheet of the workbook.
+    SheetRef(String, Box<Expr>),
+    // A name defined in the workbook, standing for a cell, a range or a
+    // constant.
+    Name(String),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Concat,
+}
+
+const OPS: [Op; 13] = [
+    Op::Add,
+    Op::Sub,
+    Op::Mul,
+    Op::Div,
+    Op::Mod,
+    Op::Pow,
+    Op::Eq,
+    Op::Ne,
+    Op::Lt,
+    Op::Gt,
+    Op::Le,
+    Op::Ge,
+    Op::Concat,
+];
+
+impl Operator for Op {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Pow => "^",
+            Op::Eq => "=",
+            Op::Ne => "<>",
+            Op::Lt => "<",
+            Op::Gt => ">",
+            Op::Le => "<=",
+            Op::Ge => ">=",
+            Op::Concat => "&",
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Op::Eq | Op::Ne | Op::Lt | Op::Gt | Op::Le | Op::Ge => 1,
+            Op::Concat => 2,
+            Op::Add | Op::Sub => 3,
+            Op::Mul | Op::Div | Op::Mod => 4,
+            Op::Pow => 5,
+        }
+    }
+}
+
+impl Op {
+    fn apply(self, x: Value, y: Value) -> Result<Value, Error> {
+        use std::cmp::Ordering;
+
+        // An error operand makes the result that error, the left one first.
+        for v in [&x, &y] {
+            if let Value::Error(e) = v {
+                return Err(e.clone());
+            }
+        }
+        if self == Op::Concat {
+            return Ok(Value::String(x.as_text()? + &y.as_text()?));
+        }
+        let x = if x == Value::Blank { y.blank_as() } else { x };
+        let y = if y == Value::Blank { x.blank_as() } else { y };
+
+        let ordering = match (&x, &y) {
+            (Value::Int(x), Value::Int(y)) => x.cmp(y),
+            (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+            // Like other spreadsheets, text compares case-insensitively.
+            (Value::String(x), Value::String(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+            (x, y) => match (x.as_f64(), y.as_f64()) {
+                (Some(x), Some(y)) => x.partial_cmp(&y).ok_or(Error::TypeError)?,
+                _ => return Err(Error::TypeError),
+            },
+        };
+        let compare = |ok: fn(Ordering) -> bool| Ok(Value::Bool(ok(ordering)));
+        match (self, x, y) {
+            (Op::Eq, _, _) => compare(|o| o == Ordering::Equal),
+            (Op::Ne, _, _) => compare(|o| o != Ordering::Equal),
+            (Op::Lt, _, _) => compare(|o| o == Ordering::Less),
+            (Op::Gt, _, _) => compare(|o| o == Ordering::Greater),
+            (Op::Le, _, _) => compare(|o| o != Ordering::Greater),
+            (Op::Ge, _, _) => compare(|o| o != Ordering::Less),
+            (_, Value::Int(x), Value::Int(y)) => self.apply_ints(x, y),
+            // Mixing an integer with a float promotes it to a float.
+            (_, x, y) => match (x.as_f64(), y.as_f64()) {
+                (Some(x), Some(y)) => self.apply_floats(x, y),
+                _ => Err(Error::TypeError),
+            },
+        }
+    }
+
+    // Results stay whole where they can, and otherwise become floats.
+    fn apply_ints(self, x: i64, y: i64) -> Result<Value, Error> {
+        use std::convert::TryFrom;
+
+        let result = match self {
+            Op::Add => x.checked_add(y),
+            Op::Sub => x.checked_sub(y),
+            Op::Mul => x.checked_mul(y),
+            Op::Div | Op::Mod if y == 0 => return Err(Error::DivByZero),
+            Op::Div if x.checked_rem(y) != Some(0) => return self.apply_floats(x as f64, y as f64),
+            Op::Div => x.checked_div(y),
+            // The result takes the sign of the divisor, as in other spreadsheets.
+            Op::Mod => x.checked_rem(y).map(|r| {
+                if r != 0 && (r < 0) != (y < 0) {
+                    r + y
+                } else {
+                    r
+                }
+            }),
+            Op::Pow if y < 0 => return self.apply_floats(x as f64, y as f64),
+            Op::Pow => u32::try_from(y).ok().and_then(|y| x.checked_pow(y)),
+            _ => unreachable!("comparisons are handled by apply"),
+        };
+        result.map(Value::Int).ok_or(Error::Overflow)
+    }
+
+    fn apply_floats(self, x: f64, y: f64) -> Result<Value, Error> {
+        let result = match self {
+            Op::Add => x + y,
+            Op::Sub => x - y,
+            Op::Mul => x * y,
+            Op::Div | Op::Mod if y == 0.0 => return Err(Error::DivByZero),
+            Op::Pow if x == 0.0 && y < 0.0 => return Err(Error::DivByZero),
+            Op::Div => x / y,
+            Op::Mod => x - y * (x / y).floor(),
+            Op::Pow => x.powf(y),
+            _ => unreachable!("comparisons are handled by apply"),
+        };
+        if result.is_nan() {
+            Err(Error::DescriptiveError(format!(
+                "{}{}{} is not a real number",
+                format_float(x),
+                self.symbol(),
+                format_float(y)
+            )))
+        } else if result.is_infinite() {
+            Err(Error::Overflow)
+        } else {
+            Ok(Value::Float(result))
+        }
+    }
+}
+
+// A cell reference within a formula. Absolute coordinates (written with a
+// `$`) stay put when the formula is copied to another cell, while relative
+// ones move along with it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+struct Reference {
+    x: usize,
+    y: usize,
+    abs_x: bool,
+    abs_y: bool,
+}
+
+impl Reference {
+    fn absolute(x: usize, y: usize) -> Reference {
+        Reference {
+            x,
+            y,
+            abs_x: true,
+            abs_y: true,
+        }
+    }
+
+    fn shift(&self, dx: isize, dy: isize) -> Result<Reference, Error> {
+        fn shift_one(v: usize, d: isize, abs: bool) -> Result<usize, Error> {
+            if abs {
+                return Ok(v);
+            }
+            (v as isize)
+                .checked_add(d)
+                .filter(|v| *v >= 0)
+                .map(|v| v as usize)
+                .ok_or_else(|| Error::DescriptiveError("reference moved off the sheet".to_string()))
+        }
+        Ok(Reference {
+            x: shift_one(self.x, dx, self.abs_x)?,
+            y: shift_one(self.y, dy, self.abs_y)?,
+            ..*self
+        })
+    }
+}
+
+impl Reference {
+    // Structural edits move absolute references too, since the cells they
+    // refer to move.
+    fn adjust(&self, edit: Edit) -> Option<Reference> {
+        let to = edit.apply(CellRef(self.x, self.y))?;
+        Some(Reference {
+            x: to.0,
+            y: to.1,
+            ..*self
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dollar = |abs| if abs { "$" } else { "" };
+        write!(
+            f,
+            "{}{}{}{}",
+            dollar(self.abs_x),
+            column_name(self.x),
+            dollar(self.abs_y),
+            self.y + 1
+        )
+    }
+}
+
+// Formats an expression back into formula text, without the leading "=".
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Blank => Ok(()),
+            Expr::Int(x) => write!(f, "{}", x),
+            // Debug formatting keeps a decimal point or exponent, so that the
+            // number reads back as a float.
+            Expr::Float(x) => write!(f, "{:?}", x),
+            Expr::Bool(b) => write!(f, "{}", b),
+            Expr::String(s) => write!(f, "\"{}\"", s.replace('"', "\"\"")),
+            Expr::Error(e) => write!(f, "{}", e.code()),
+            Expr::Neg(x) => match **x {
+                Expr::BinOp(..) => write!(f, "-({})", x),
+                _ => write!(f, "-{}", x),
+            },
+            Expr::BinOp(op, x, y) => {
+                // Operators are left associative, so a right operand of the
+                // same precedence needs parentheses too.
+                let needs_parens = |e: &Expr, right: bool| match e {
+                    Expr::BinOp(o, _, _) => {
+                        o.precedence() < op.precedence()
+                            || (right && o.precedence() == op.precedence())
+                    }
+                    _ => false,
+                };
+                let wrap = |e: &Expr, right| match needs_parens(e, right) {
+                    true => format!("({})", e),
+                    false => e.to_string(),
+                };
+                write!(f, "{}{}{}", wrap(x, false), op.symbol(), wrap(y, true))
+            }
+            Expr::If(b, x, y) => write!(f, "if({},{},{})", b, x, y),
+            Expr::FnCall(name, args) => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", name, args.join(","))
+            }
+            Expr::CellRef(r) => write!(f, "{}", r),
+            Expr::Range(r1, r2) => write!(f, "{}:{}", r1, r2),
+            Expr::SheetRef(name, r) => write!(f, "{}!{}", quote_sheet_name(name), r),
+            Expr::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// Sheet names which aren't plain identifiers are quoted, like 'Q1 Sales'.
+fn quote_sheet_name(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match plain {
+        true => name.to_string(),
+        false => format!("'{}'", name.replace('\'', "''")),
+    }
+}
+
+impl Expr {
+    fn parse(s: &str) -> Result<Expr, Error> {
+        let p = Parsing::new(s)?.expr()?.done()?;
+        Ok(p.get())
+    }
+
+    fn eval(&self, sheet: &Spreadsheet) -> Result<Value, Error> {
+        match self {
+            Expr::Int(x) => Ok(Value::Int(*x)),
+            Expr::Float(x) => Ok(Value::Float(*x)),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Blank => Ok(Value::Blank),
+            Expr::String(s) => Ok(Value::String(s.clone())),
+            Expr::Error(e) => Err(e.clone()),
+
+            Expr::Neg(x) => x.eval(sheet)?.map(|x| match x {
+                Value::Int(x) => x.checked_neg().map(Value::Int).ok_or(Error::Overflow),
+                Value::Float(x) => Ok(Value::Float(-x)),
+                Value::Blank => Ok(Value::Int(0)),
+                Value::Error(e) => Err(e),
+                _ => Err(Error::TypeError),
+            }),
+            Expr::BinOp(op, x, y) => x
+                .eval(sheet)?
+                .zip_with(y.eval(sheet)?, |x, y| op.apply(x, y)),
+            Expr::If(b, x, y) => match b.eval(sheet)? {
+                Value::Bool(b) => Ok(if b { x.eval(sheet)? } else { y.eval(sheet)? }),
+                // A blank condition counts as false.
+                Value::Blank => y.eval(sheet),
+                Value::Array(rows) => {
+                    // Pick from each branch element by element, only evaluating
+                    // the branches which are picked from at least once. An
+                    // error in a branch only affects the elements picked from it.
+                    let flat = || rows.iter().flatten();
+                    let x = match flat().any(|v| *v == Value::Bool(true)) {
+                        true => x.eval(sheet).unwrap_or_else(Value::Error),
+                        false => Value::Bool(false),
+                    };
+                    let y = match flat().any(|v| matches!(v, Value::Bool(false) | Value::Blank)) {
+                        true => y.eval(sheet).unwrap_or_else(Value::Error),
+                        false => Value::Bool(false),
+                    };
+                    let mut out = vec![];
+                    for (i, row) in rows.iter().enumerate() {
+                        let mut out_row = vec![];
+                        for (j, b) in row.iter().enumerate() {
+                            out_row.push(match b {
+                                Value::Bool(true) => x.element(i, j)?,
+                                Value::Bool(false) | Value::Blank => y.element(i, j)?,
+                                Value::Error(e) => Value::Error(e.clone()),
+                                _ => Value::Error(Error::TypeError),
+                            });
+                        }
+                        out.push(out_row);
+                    }
+                    Ok(Value::Array(out))
+                }
+                _ => Err(Error::TypeError),
+            },
+            Expr::FnCall(name, args) => match functions::lookup(name) {
+                None => Err(Error::NameError(name.clone())),
+                Some(f) => f(functions::Args::new(name, args, sheet)),
+            },
+            Expr::CellRef(_) | Expr::Range(..) => self.eval_ref(|x, y| sheet.cell_value(x, y)),
+            // Other sheets' values come from the copies the workbook keeps in
+            // the sheet. A sheet on its own has none, nor does a workbook for
+            // sheets it doesn't have.
+            Expr::SheetRef(name, r) => match sheet.imports.get(name) {
+                Some(cells) => {
+                    r.eval_ref(|x, y| cells.get(&CellRef(x, y)).cloned().unwrap_or(Value::Blank))
+                }
+                None => Err(Error::RefError),
+            },
+            // Likewise for the definitions of names.
+            Expr::Name(name) => match sheet.names.get(&name.to_lowercase()) {
+                Some(definition) => definition.eval(sheet),
+                None => Err(Error::NameError(name.clone())),
+            },
+        }
+    }
+
+    // Evaluates a cell reference or range, given the values of the cells.
+    fn eval_ref(&self, value: impl Fn(usize, usize) -> Value) -> Result<Value, Error> {
+        match self {
+            Expr::CellRef(r) => match value(r.x, r.y) {
+                Value::Error(e) => Err(e),
+                v => Ok(v),
+            },
+            // Errors in the range's cells are kept as elements of the array.
+            // Ranges too big to hold as an array give #NUM! errors.
+            Expr::Range(r1, r2) => {
+                let area = (r2.x - r1.x + 1).saturating_mul(r2.y - r1.y + 1);
+                if area > MAX_ARRAY_CELLS {
+                    return Err(Error::Overflow);
+                }
+                let rows = (r1.y..=r2.y)
+                    .map(|y| (r1.x..=r2.x).map(|x| value(x, y)).collect())
+                    .collect();
+                Ok(Value::Array(rows))
+            }
+            _ => Err(Error::RefError),
+        }
+    }
+
+    // The values of the filled cells in a range, in row order, or None if
+    // the expression isn't a range. This takes time in proportion to the
+    // size of the range or the number of filled cells in the sheet,
+    // whichever is smaller, for functions which skip blanks anyway.
+    fn filled_values(&self, sheet: &Spreadsheet) -> Option<Vec<Value>> {
+        match self {
+            Expr::Range(r1, r2) => Some(filled(r1, r2, &sheet.cells, |c| &c.value)),
+            Expr::SheetRef(name, r) => match (sheet.imports.get(name), &**r) {
+                (Some(cells), Expr::Range(r1, r2)) => Some(filled(r1, r2, cells, |v| v)),
+                _ => None,
+            },
+            Expr::Name(name) => sheet.names.get(&name.to_lowercase())?.filled_values(sheet),
+            _ => None,
+        }
+    }
+
+    // Moves every relative reference by (dx, dy), as when copying a formula.
+    fn shift(&self, dx: isize, dy: isize) -> Result<Expr, Error> {
+        let shift = |e: &Expr| e.shift(dx, dy).map(Box::new);
+        Ok(match self {
+            Expr::Blank
+            | Expr::Int(_)
+            | Expr::Float(_)
+            | Expr::Bool(_)
+            | Expr::String(_)
+            | Expr::Error(_)
+            | Expr::Name(_) => self.clone(),
+            Expr::Neg(x) => Expr::Neg(shift(x)?),
+            Expr::BinOp(op, x, y) => Expr::BinOp(*op, shift(x)?, shift(y)?),
+            Expr::If(b, x, y) => Expr::If(shift(b)?, shift(x)?, shift(y)?),
+            Expr::FnCall(name, args) => {
+                let args: Result<Vec<Expr>, Error> = args.iter().map(|a| a.shift(dx, dy)).collect();
+                Expr::FnCall(name.clone(), args?)
+            }
+            Expr::CellRef(r) => Expr::CellRef(r.shift(dx, dy)?),
+            Expr::Range(r1, r2) => Expr::Range(r1.shift(dx, dy)?, r2.shift(dx, dy)?),
+            Expr::SheetRef(name, r) => Expr::SheetRef(name.clone(), shift(r)?),
+        })
+    }
+
+    // Rewrites references to follow the cells they refer to through `edit`.
+    // References to other sheets are left alone, since the edit is on this one.
+    fn adjust(&self, edit: Edit) -> Expr {
+        let adjust = |e: &Expr| Box::new(e.adjust(edit));
+        match self {
+            Expr::Neg(x) => Expr::Neg(adjust(x)),
+            Expr::BinOp(op, x, y) => Expr::BinOp(*op, adjust(x), adjust(y)),
+            Expr::If(b, x, y) => Expr::If(adjust(b), adjust(x), adjust(y)),
+            Expr::FnCall(name, args) => {
+                Expr::FnCall(name.clone(), args.iter().map(|a| a.adjust(edit)).collect())
+            }
+            Expr::CellRef(r) => match r.adjust(edit) {
+                Some(r) => Expr::CellRef(r),
+                None => Expr::Error(Error::RefError),
+            },
+            Expr::Range(r1, r2) => {
+                let (start, end) = match edit.axis() {
+                    Axis::Col => (r1.x, r2.x),
+                    Axis::Row => (r1.y, r2.y),
+                };
+                match edit.span(start, end) {
+                    Some((start, end)) => {
+                        let (mut r1, mut r2) = (*r1, *r2);
+                        match edit.axis() {
+                            Axis::Col => (r1.x, r2.x) = (start, end),
+                            Axis::Row => (r1.y, r2.y) = (start, end),
+                        }
+                        Expr::Range(r1, r2)
+                    }
+                    None => Expr::Error(Error::RefError),
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+
+    // Calls `f` on every cell reference, range and name in the expression.
+    // References to other sheets are passed as a whole, without their inner
+    // reference.
+    fn visit_refs(&self, f: &mut impl FnMut(&Expr)) {
+        match self {
+            Expr::Neg(x) => x.visit_refs(f),
+            Expr::BinOp(_, x, y) => {
+                x.visit_refs(f);
+                y.visit_refs(f);
+            }
+            Expr::If(b, x, y) => {
+                b.visit_refs(f);
+                x.visit_refs(f);
+                y.visit_refs(f);
+            }
+            Expr::FnCall(_, args) => args.iter().for_each(|a| a.visit_refs(f)),
+            Expr::CellRef(_) | Expr::Range(..) | Expr::SheetRef(..) | Expr::Name(_) => f(self),
+            _ => (),
+        }
+    }
 
-    Plus(Box<Expr>, Box<Expr>),
-    Eq(Box<Expr>, Box<Expr>),
-    If(Box<Expr>, Box<Expr>, Box<Expr>),
-    FnCall(String, Vec<Expr>),
-    CellRef(usize, usize),
-}
+    // Returns the single cells this expression refers to, without duplicates.
+    fn cell_refs(&self) -> Vec<CellRef> {
+        let mut out = vec![];
+        self.visit_refs(&mut |e| {
+            if let Expr::CellRef(r) = e {
+                out.push(CellRef(r.x, r.y));
+            }
+        });
+        out.sort_by_key(|r| (r.1, r.0));
+        out.dedup();
+        out
+    }
 
-impl Expr {
-    fn parse(s: &str) -> Result<Expr, Error> {
-        let p = Parsing::new(s.to_string()).expr()?.done()?;
-        Ok(p.get())
+    // Returns the ranges this expression refers to, without duplicates.
+    fn cell_ranges(&self) -> Vec<CellRange> {
+        let mut out = vec![];
+        self.visit_refs(&mut |e| {
+            if let Expr::Range(r1, r2) = e {
+                let range = CellRange(CellRef(r1.x, r1.y), CellRef(r2.x, r2.y));
+                if !out.contains(&range) {
+                    out.push(range);
+                }
+            }
+        });
+        out
     }
 
-    fn eval(&self, sheet: &Spreadsheet) -> Result<Value, Error> {
-        match self {
-            Expr::Int(x) => Ok(Value::Int(*x)),
-            Expr::Bool(b) => Ok(Value::Bool(*b)),
-            Expr::String(s) => Ok(Value::String(s.clone())),
+    // Returns the cells this expression refers to on other sheets, by sheet
+    // name, without duplicates. Single cells are ranges of one cell.
+    fn sheet_refs(&self) -> Vec<(String, CellRange)> {
+        let mut out = vec![];
+        self.visit_refs(&mut |e| {
+            let (name, range) = match e {
+                Expr::SheetRef(name, r) => match **r {
+                    Expr::CellRef(r) => (name, CellRange(CellRef(r.x, r.y), CellRef(r.x, r.y))),
+                    Expr::Range(r1, r2) => {
+                        (name, CellRange(CellRef(r1.x, r1.y), CellRef(r2.x, r2.y)))
+                    }
+                    _ => return,
+                },
+                _ => return,
+            };
+            if !out.iter().any(|(n, r)| n == name && *r == range) {
+                out.push((name.clone(), range));
+            }
+        });
+        out
+    }
 
-            Expr::Plus(x, y) => match (x.eval(sheet)?, y.eval(sheet)?) {
-                (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x + y)),
-                _ => Err(Error::TypeError),
-            },
-            Expr::Eq(x, y) => match (x.eval(sheet)?, y.eval(sheet)?) {
-                (Value::Int(x), Value::Int(y)) => Ok(Value::Bool(x == y)),
-                _ => Err(Error::TypeError),
-            },
-            Expr::If(b, x, y) => match b.eval(sheet)? {
-                Value::Bool(b) => Ok(if b { x.eval(sheet)? } else { y.eval(sheet)? }),
-                _ => Err(Error::TypeError),
-            },
-            Expr::FnCall(name, args) => match BUILT_INS.get(name) {
-                None => Err(Error::DescriptiveError(format!(
-                    "function \"{}\" does not exist",
-                    name
-                ))),
-                Some(f) => {
-                    let vals: Result<Vec<Value>, Error> =
-                        args.iter().map(|x| x.eval(sheet)).collect();
-                    Ok(f(vals?))
+    // Returns the names this expression uses, in lowercase and without
+    // duplicates, since names are matched without regard to case.
+    fn names(&self) -> Vec<String> {
+        let mut out = vec![];
+        self.visit_refs(&mut |e| {
+            if let Expr::Name(name) = e {
+                let name = name.to_lowercase();
+                if !out.contains(&name) {
+                    out.push(name);
                 }
-            },
-            Expr::CellRef(x, y) => sheet.get_expr(*x, *y)?.eval(sheet),
+            }
+        });
+        out
+    }
+
+    // Replaces each reference to another sheet with what `f` makes of it.
+    fn map_sheet_refs(&self, f: &impl Fn(&str, &Expr) -> Expr) -> Expr {
+        let map = |e: &Expr| Box::new(e.map_sheet_refs(f));
+        match self {
+            Expr::Neg(x) => Expr::Neg(map(x)),
+            Expr::BinOp(op, x, y) => Expr::BinOp(*op, map(x), map(y)),
+            Expr::If(b, x, y) => Expr::If(map(b), map(x), map(y)),
+            Expr::FnCall(name, args) => Expr::FnCall(
+                name.clone(),
+                args.iter().map(|a| a.map_sheet_refs(f)).collect(),
+            ),
+            Expr::SheetRef(name, r) => f(name, r),
+            _ => self.clone(),
         }
     }
 }
 
 mod parsing;
-use parsing::{ParseResult, Parsing, Transformer, P};
+use parsing::{Operator, ParseResult, Parsing, TokenKind, Transformer, P};
 
-// TODO: Skip unimportant whitespace. Maybe by adding a tokenizer?
 impl<T: Clone> P<T> {
-    fn e_int(self) -> ParseResult<Expr> {
-        let p = self.parse_int()?;
-        let e = Expr::Int(p.get());
-        Ok(p.replace(e))
+    fn e_number(self) -> ParseResult<Expr> {
+        self.number("")
+    }
+
+    // Reads a number token, putting `sign` in front of it.
+    fn number(self, sign: &str) -> ParseResult<Expr> {
+        let pos = self.pos();
+        let p = self.match_pred(|t| t.kind == TokenKind::Number, "a number")?;
+        match number_literal(&format!("{}{}", sign, p.get())) {
+            Some(e) => Ok(p.replace(e)),
+            None => Err(parsing::Error::new(
+                format!("\"{}\" is too large", p.get()),
+                pos,
+            )),
+        }
+    }
+
+    // A doubled quote within a string stands for one quote character.
+    fn e_string(self) -> ParseResult<Expr> {
+        let p = self.match_pred(|t| t.kind == TokenKind::String, "a string")?;
+        let quoted = p.get();
+        let s = quoted[1..quoted.len() - 1].replace("\"\"", "\"");
+        Ok(p.replace(Expr::String(s)))
+    }
+
+    fn e_error(self) -> ParseResult<Expr> {
+        let pos = self.pos();
+        let p = self.match_pred(|t| t.kind == TokenKind::ErrorCode, "an error value")?;
+        match Error::from_code(&p.get()) {
+            Some(e) => Ok(p.replace(Expr::Error(e))),
+            None => Err(parsing::Error::new(
+                format!("\"{}\" is not an error value", p.get()),
+                pos,
+            )),
+        }
     }
 
     fn e_bool(self) -> ParseResult<Expr> {
@@ -215,26 +1631,24 @@ impl<T: Clone> P<T> {
         ])
     }
 
-    fn e_plus(self) -> ParseResult<Expr> {
-        let inner: Transformer<T, Expr> = |p| {
-            let p1 = p.expr()?;
-            let e1 = Box::new(p1.get());
-            let p2 = p1.skip("+")?.expr()?;
-            let e2 = Box::new(p2.get());
-            Ok(p2.replace(Expr::Plus(e1, e2)))
-        };
-        self.wrapped("(", inner, ")")
+    fn e_paren(self) -> ParseResult<Expr> {
+        self.wrapped("(", |p| p.expr(), ")")
     }
 
-    fn e_eq(self) -> ParseResult<Expr> {
-        let inner: Transformer<T, Expr> = |p| {
-            let p1 = p.expr()?;
-            let e1 = Box::new(p1.get());
-            let p2 = p1.skip("=")?.expr()?;
-            let e2 = Box::new(p2.get());
-            Ok(p2.replace(Expr::Eq(e1, e2)))
-        };
-        self.wrapped("(", inner, ")")
+    fn e_neg(self) -> ParseResult<Expr> {
+        let p = self.skip("-")?;
+        // A minus sign right before a number is part of the literal.
+        if p.peek().is_some_and(|t| t.kind == TokenKind::Number) {
+            return p.number("-");
+        }
+        let p = p.e_unary()?;
+        let e = Expr::Neg(Box::new(p.get()));
+        Ok(p.replace(e))
+    }
+
+    // Negation binds more tightly than any binary operator, so -2^2 is 4.
+    fn e_unary(self) -> ParseResult<Expr> {
+        self.try_one(vec![|p| p.e_neg(), |p| p.e_atom()])
     }
 
     fn e_if(self) -> ParseResult<Expr> {
@@ -256,23 +1670,108 @@ impl<T: Clone> P<T> {
             let a1 = p.get() as usize;
             let p = p.skip(",")?.parse_int()?;
             let a2 = p.get() as usize;
-            Ok(p.replace(Expr::CellRef(a1, a2)))
+            Ok(p.replace(Expr::CellRef(Reference::absolute(a1, a2))))
         };
         self.skip("Ref")?.wrapped("(", args, ")")
     }
 
+    fn e_range(self) -> ParseResult<Expr> {
+        let p = self.e_single_ref()?;
+        let start = p.get();
+        let p = p.skip(":")?.e_single_ref()?;
+        match (start, p.get()) {
+            (Expr::CellRef(r1), Expr::CellRef(r2)) => {
+                // Put the corners in order, keeping each coordinate's flag.
+                let (x1, abs_x1, x2, abs_x2) = match r1.x <= r2.x {
+                    true => (r1.x, r1.abs_x, r2.x, r2.abs_x),
+                    false => (r2.x, r2.abs_x, r1.x, r1.abs_x),
+                };
+                let (y1, abs_y1, y2, abs_y2) = match r1.y <= r2.y {
+                    true => (r1.y, r1.abs_y, r2.y, r2.abs_y),
+                    false => (r2.y, r2.abs_y, r1.y, r1.abs_y),
+                };
+                Ok(p.replace(Expr::Range(
+                    Reference {
+                        x: x1,
+                        y: y1,
+                        abs_x: abs_x1,
+                        abs_y: abs_y1,
+                    },
+                    Reference {
+                        x: x2,
+                        y: y2,
+                        abs_x: abs_x2,
+                        abs_y: abs_y2,
+                    },
+                )))
+            }
+            _ => unreachable!("e_single_ref only returns cell refs"),
+        }
+    }
+
+    fn e_single_ref(self) -> ParseResult<Expr> {
+        self.try_one(vec![|p| p.e_cell_ref(), |p| p.e_a1_ref()])
+    }
+
+    fn e_a1_ref(self) -> ParseResult<Expr> {
+        let pos = self.pos();
+        let p = self.ident()?;
+        match parse_reference(&p.get()) {
+            Some(r) => Ok(p.replace(Expr::CellRef(r))),
+            None => Err(parsing::Error::new(
+                format!("\"{}\" is not a valid cell address", p.get()),
+                pos,
+            )),
+        }
+    }
+
+    // A reference to a cell or range on another sheet, like `Data!A1:B2` or
+    // `'Q1 Sales'!A1`.
+    fn e_sheet_ref(self) -> ParseResult<Expr> {
+        let p = self.match_pred(
+            |t| t.kind == TokenKind::Ident || t.kind == TokenKind::SheetName,
+            "a sheet name",
+        )?;
+        let name = p.get();
+        let name = match name.strip_prefix('\'') {
+            Some(quoted) => quoted[..quoted.len() - 1].replace("''", "'"),
+            None => name,
+        };
+        let p = p
+            .skip("!")?
+            .try_one(vec![|p| p.e_range(), |p| p.e_single_ref()])?;
+        let r = p.get();
+        Ok(p.replace(Expr::SheetRef(name, Box::new(r))))
+    }
+
+    // A name followed by arguments in brackets is a function call. Without
+    // them it's a name defined in the workbook, unless it's a cell address.
     fn e_fn_call(self) -> ParseResult<Expr> {
-        let p = self.match_pred(u8::is_ascii_alphanumeric, "is_ascii_alphanumeric")?;
+        let pos = self.pos();
+        let p = self.ident()?;
         let name: String = p.get();
+        if p.peek().is_none_or(|t| t.text != "(") {
+            return match is_address_like(&name) {
+                true => Err(parsing::Error::new(
+                    format!("\"{}\" is not a valid cell address", name),
+                    pos,
+                )),
+                false => Ok(p.replace(Expr::Name(name))),
+            };
+        }
 
         fn parse_args<T1: Clone>(p: Parsing<T1>) -> ParseResult<Vec<Expr>> {
-            fn arg_then_comma(p: Parsing<()>) -> ParseResult<Expr> {
-                p.expr()?.skip(",")
+            if p.peek().is_some_and(|t| t.text == ")") {
+                return Ok(p.replace(vec![]));
             }
-            let p = p.repeat(arg_then_comma)?;
-            let mut args: Vec<Expr> = p.get();
             let p = p.expr()?;
-            args.push(p.get());
+            let mut args = vec![p.get()];
+            // Any further arguments each follow a comma.
+            let p = match p.peek() {
+                Some(t) if t.text == "," => p.repeat(|p| p.skip(",")?.expr())?,
+                _ => p.replace(vec![]),
+            };
+            args.extend(p.get());
             Ok(p.replace(args))
         }
         let p = p.wrapped("(", parse_args, ")")?;
@@ -280,30 +1779,104 @@ impl<T: Clone> P<T> {
         Ok(p.replace(Expr::FnCall(name, args)))
     }
 
-    fn expr(self) -> ParseResult<Expr> {
+    fn e_atom(self) -> ParseResult<Expr> {
         self.try_one(vec![
-            |p| p.e_int(),
+            |p| p.e_number(),
+            |p| p.e_string(),
+            |p| p.e_error(),
+            |p| p.e_sheet_ref(),
             |p| p.e_bool(),
-            |p| p.e_plus(),
-            |p| p.e_eq(),
+            |p| p.e_paren(),
             |p| p.e_if(),
+            |p| p.e_range(),
             |p| p.e_cell_ref(),
             |p| p.e_fn_call(),
+            |p| p.e_a1_ref(),
         ])
     }
+
+    fn expr(self) -> ParseResult<Expr> {
+        self.climb(
+            |p| p.e_unary(),
+            &OPS,
+            |op, x, y| Expr::BinOp(op, Box::new(x), Box::new(y)),
+        )
+    }
+}
+
+// Whole numbers which fit in an integer stay exact. Anything else, like
+// "1.5", "2e3" or a huge whole number, becomes a float.
+fn number_literal(s: &str) -> Option<Expr> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    if digits.bytes().all(|c| c.is_ascii_digit()) {
+        if let Ok(x) = s.parse::<i64>() {
+            return Some(Expr::Int(x));
+        }
+    }
+    s.parse::<f64>()
+        .ok()
+        .filter(|x| x.is_finite())
+        .map(Expr::Float)
 }
 
-type BuiltIn = fn(Vec<Value>) -> Value;
-use lazy_static::lazy_static;
-lazy_static! {
-    static ref BUILT_INS: HashMap<String, BuiltIn> = hashmap! {};
+mod csv;
+mod functions;
+mod history;
+mod input;
+mod json;
+mod native;
+mod ods;
+mod workbook;
+mod xlsx;
+
+pub use csv::CsvOptions;
+pub use json::{JsonOptions, JsonShape};
+pub use ods::{read_ods, write_ods};
+pub use workbook::Workbook;
+pub use xlsx::{read_xlsx, write_xlsx};
+
+use history::{Command, History};
+
+// The most cells a range can have when its values are needed as an array.
+const MAX_ARRAY_CELLS: usize = 1 << 20;
+
+// The values of the cells from `cells` which are inside the range with the
+// given corners, in row order. A small range is looked up cell by cell, and
+// a big one is found by going through the cells there are.
+fn filled<T>(
+    r1: &Reference,
+    r2: &Reference,
+    cells: &HashMap<CellRef, T>,
+    value: impl Fn(&T) -> &Value,
+) -> Vec<Value> {
+    let area = (r2.x - r1.x + 1).saturating_mul(r2.y - r1.y + 1);
+    if area <= cells.len() {
+        return (r1.y..=r2.y)
+            .flat_map(|y| (r1.x..=r2.x).map(move |x| CellRef(x, y)))
+            .filter_map(|r| cells.get(&r))
+            .map(|c| value(c).clone())
+            .collect();
+    }
+    let range = CellRange(CellRef(r1.x, r1.y), CellRef(r2.x, r2.y));
+    let mut found: Vec<(&CellRef, &T)> =
+        cells.iter().filter(|(r, _)| range.contains(**r)).collect();
+    found.sort_by_key(|(r, _)| (r.1, r.0));
+    found.into_iter().map(|(_, c)| value(c).clone()).collect()
 }
 
 #[derive(Debug, PartialEq, Clone)]
 enum Value {
+    // The value of an empty cell.
+    Blank,
     Int(i64),
+    Float(f64),
     Bool(bool),
     String(String),
+    // Rows of values, as produced by a range.
+    Array(Vec<Vec<Value>>),
+    // Evaluation errors are only kept as values within arrays and cells.
+    // Elsewhere they're returned as an `Err`.
+    Error(Error),
 }
 
 impl fmt::Display for Value {
@@ -312,25 +1885,198 @@ impl fmt::Display for Value {
             f,
             "{}",
             match self {
+                Value::Blank => String::new(),
                 Value::Int(x) => x.to_string(),
+                Value::Float(x) => format_float(*x),
                 Value::Bool(b) => b.to_string(),
                 Value::String(s) => format!("\"{}\"", s),
+                Value::Error(e) => e.code().to_string(),
+                Value::Array(rows) => {
+                    let rows: Vec<String> = rows
+                        .iter()
+                        .map(|row| {
+                            let row: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+                            row.join(",")
+                        })
+                        .collect();
+                    format!("{{{}}}", rows.join(";"))
+                }
             }
         )
     }
 }
 
-#[derive(Debug, Clone)]
+// Shows at most 15 significant digits, like other spreadsheets, so that
+// 0.1+0.2 shows as 0.3. Very large and very small magnitudes use scientific
+// notation.
+fn format_float(x: f64) -> String {
+    fn trim_fraction(s: &str) -> &str {
+        match s.contains('.') {
+            true => s.trim_end_matches('0').trim_end_matches('.'),
+            false => s,
+        }
+    }
+    let abs = x.abs();
+    if abs == 0.0 {
+        "0".to_string()
+    } else if (1e-9..1e15).contains(&abs) {
+        let decimals = (14 - abs.log10().floor() as i32).max(0) as usize;
+        trim_fraction(&format!("{:.*}", decimals, x)).to_string()
+    } else {
+        let s = format!("{:.14e}", x);
+        let (mantissa, exp) = s.split_once('e').unwrap();
+        format!("{}e{}", trim_fraction(mantissa), exp)
+    }
+}
+
+impl Value {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(x) => Some(*x as f64),
+            Value::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    // What a blank stands for when combined with this value: "" beside text,
+    // false beside a boolean, and 0 otherwise.
+    fn blank_as(&self) -> Value {
+        match self {
+            Value::String(_) => Value::String(String::new()),
+            Value::Bool(_) => Value::Bool(false),
+            _ => Value::Int(0),
+        }
+    }
+
+    // Converts a scalar to text, as for concatenation.
+    fn as_text(&self) -> Result<String, Error> {
+        match self {
+            Value::Blank => Ok(String::new()),
+            Value::Int(x) => Ok(x.to_string()),
+            Value::Float(x) => Ok(format_float(*x)),
+            Value::Bool(b) => Ok(b.to_string()),
+            Value::String(s) => Ok(s.clone()),
+            Value::Array(_) => Err(Error::TypeError),
+            Value::Error(e) => Err(e.clone()),
+        }
+    }
+
+    // The (height, width) of an array, or None for a scalar.
+    fn dims(&self) -> Option<(usize, usize)> {
+        match self {
+            Value::Array(rows) => Some((rows.len(), rows.first().map_or(0, |r| r.len()))),
+            _ => None,
+        }
+    }
+
+    // Scalars act like arrays holding themselves in every position.
+    fn element(&self, i: usize, j: usize) -> Result<Value, Error> {
+        match self {
+            Value::Array(rows) => rows.get(i).and_then(|r| r.get(j)).cloned().ok_or_else(|| {
+                Error::DescriptiveError(format!("array has no element at {},{}", i, j))
+            }),
+            v => Ok(v.clone()),
+        }
+    }
+
+    // Applies `f` to a value, element by element if it's an array. Errors
+    // from `f` become error elements.
+    fn map(self, f: impl Fn(Value) -> Result<Value, Error>) -> Result<Value, Error> {
+        match self {
+            Value::Array(rows) => Ok(Value::Array(
+                rows.into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .map(|v| f(v).unwrap_or_else(Value::Error))
+                            .collect()
+                    })
+                    .collect(),
+            )),
+            v => f(v),
+        }
+    }
+
+    // Combines two values with `f`, element by element if either is an array.
+    // Errors from `f` become error elements.
+    fn zip_with(
+        self,
+        other: Value,
+        f: impl Fn(Value, Value) -> Result<Value, Error>,
+    ) -> Result<Value, Error> {
+        let (h, w) = match (self.dims(), other.dims()) {
+            (None, None) => return f(self, other),
+            (Some(d1), Some(d2)) if d1 != d2 => {
+                return Err(Error::DescriptiveError(format!(
+                    "can't combine a {}x{} array with a {}x{} array",
+                    d1.1, d1.0, d2.1, d2.0
+                )))
+            }
+            (Some(d), _) | (_, Some(d)) => d,
+        };
+        let mut rows = vec![];
+        for i in 0..h {
+            let mut row = vec![];
+            for j in 0..w {
+                let v = f(self.element(i, j)?, other.element(i, j)?);
+                row.push(v.unwrap_or_else(Value::Error));
+            }
+            rows.push(row);
+        }
+        Ok(Value::Array(rows))
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
     //ParseError(Box<dyn error::Error>),
     DescriptiveError(String),
+    // A formula couldn't be parsed. Holds the (one-based) column and the problem.
+    ParseError(usize, String),
     TypeError,
+    CircularReference,
+    DivByZero,
+    Overflow,
+    // A reference to a cell which doesn't exist.
+    RefError,
+    // A function or other name which isn't defined. Empty if it's unknown
+    // which name it was, as for a "#NAME?" typed into a formula.
+    NameError(String),
+    NotAvailable,
+}
+
+impl Error {
+    // The short code shown in place of a value, like in other spreadsheets.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::DescriptiveError(_) | Error::TypeError => "#VALUE!",
+            Error::ParseError(..) => "#ERROR!",
+            Error::CircularReference => "#CIRC!",
+            Error::DivByZero => "#DIV/0!",
+            Error::Overflow => "#NUM!",
+            Error::RefError => "#REF!",
+            Error::NameError(_) => "#NAME?",
+            Error::NotAvailable => "#N/A",
+        }
+    }
+
+    // The inverse of `code`, for error values written into formulas.
+    fn from_code(code: &str) -> Option<Error> {
+        match code.to_ascii_uppercase().as_str() {
+            "#VALUE!" => Some(Error::TypeError),
+            "#DIV/0!" => Some(Error::DivByZero),
+            "#NUM!" => Some(Error::Overflow),
+            "#REF!" => Some(Error::RefError),
+            "#NAME?" => Some(Error::NameError(String::new())),
+            "#N/A" => Some(Error::NotAvailable),
+            _ => None,
+        }
+    }
 }
 
 impl From<parsing::Error> for Error {
     fn from(e: parsing::Error) -> Error {
-        let parsing::Error(s) = e;
-        Error::DescriptiveError(s)
+        Error::ParseError(e.pos + 1, e.msg)
     }
 }
 
@@ -340,9 +2086,35 @@ impl From<num::ParseIntError> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::DescriptiveError(e.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Error {
+        Error::DescriptiveError(e.to_string())
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(e: quick_xml::Error) -> Error {
+        Error::DescriptiveError(e.to_string())
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::DescriptiveError(s) => write!(f, "{}", s),
+            Error::ParseError(col, s) => write!(f, "parse error at column {}: {}", col, s),
+            Error::RefError => write!(f, "reference to a cell which doesn't exist"),
+            Error::NameError(name) if name.is_empty() => write!(f, "unknown name"),
+            Error::NameError(name) => write!(f, "unknown name \"{}\"", name),
+            Error::NotAvailable => write!(f, "value not available"),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -352,6 +2124,15 @@ mod expr_tests {
 
     type TR = Result<(), Error>;
 
+    fn rel(x: usize, y: usize) -> Reference {
+        Reference {
+            x,
+            y,
+            abs_x: false,
+            abs_y: false,
+        }
+    }
+
     #[test]
     fn test_parse_int() -> TR {
         let e = Expr::parse("52")?;
@@ -359,6 +2140,51 @@ mod expr_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_numbers() -> TR {
+        assert_eq!(Expr::parse("1.5")?, Expr::Float(1.5));
+        assert_eq!(Expr::parse(".5")?, Expr::Float(0.5));
+        assert_eq!(Expr::parse("-7")?, Expr::Int(-7));
+        assert_eq!(Expr::parse("- 2.5")?, Expr::Float(-2.5));
+        assert_eq!(Expr::parse("2E3")?, Expr::Float(2000.0));
+        assert_eq!(Expr::parse("1.5e-3")?, Expr::Float(0.0015));
+        assert_eq!(Expr::parse("-9223372036854775808")?, Expr::Int(i64::MIN));
+        assert_eq!(
+            Expr::parse("9223372036854775808")?,
+            Expr::Float(9223372036854775808.0)
+        );
+        assert!(Expr::parse("1e400").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_arithmetic() -> TR {
+        let s = Spreadsheet::new();
+        let eval = |f: &str| Expr::parse(f).and_then(|e| e.eval(&s));
+        assert_eq!(eval("1.5+1")?, Value::Float(2.5));
+        assert_eq!(eval("-1.5*2")?, Value::Float(-3.0));
+        assert_eq!(eval("2^-1")?, Value::Float(0.5));
+        assert_eq!(eval("5.5%2")?, Value::Float(1.5));
+        assert_eq!(eval("-5.5%2")?, Value::Float(0.5));
+        assert_eq!(eval("-(1.5)")?, Value::Float(-1.5));
+        assert_eq!(eval("1=1.0")?, Value::Bool(true));
+        assert_eq!(eval("2.5>2")?, Value::Bool(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_float() {
+        let show = |x: f64| Value::Float(x).to_string();
+        assert_eq!(show(3.0), "3");
+        assert_eq!(show(-0.0), "0");
+        assert_eq!(show(0.1 + 0.2), "0.3");
+        assert_eq!(show(-2.5), "-2.5");
+        assert_eq!(show(1.0 / 3.0), "0.333333333333333");
+        assert_eq!(show(123456789.125), "123456789.125");
+        assert_eq!(show(1.5e20), "1.5e20");
+        assert_eq!(show(-2e-12), "-2e-12");
+    }
+
     #[test]
     fn test_parse_bool() -> TR {
         let e = Expr::parse("true")?;
@@ -373,7 +2199,7 @@ mod expr_tests {
         let e = Expr::parse("(13+2)")?;
         assert_eq!(
             e,
-            Expr::Plus(Box::new(Expr::Int(13)), Box::new(Expr::Int(2)))
+            Expr::BinOp(Op::Add, Box::new(Expr::Int(13)), Box::new(Expr::Int(2)))
         );
         Ok(())
     }
@@ -383,11 +2209,58 @@ mod expr_tests {
         let e = Expr::parse("(13+(2+5))")?;
         assert_eq!(
             e,
-            Expr::Plus(
+            Expr::BinOp(
+                Op::Add,
                 Box::new(Expr::Int(13)),
-                Box::new(Expr::Plus(Box::new(Expr::Int(2)), Box::new(Expr::Int(5))))
+                Box::new(Expr::BinOp(
+                    Op::Add,
+                    Box::new(Expr::Int(2)),
+                    Box::new(Expr::Int(5))
+                ))
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_sheet_refs() -> TR {
+        assert_eq!(
+            Expr::parse("Data!$A1")?,
+            Expr::SheetRef(
+                "Data".to_string(),
+                Box::new(Expr::CellRef(Reference {
+                    abs_x: true,
+                    ..rel(0, 0)
+                }))
+            )
+        );
+        assert_eq!(
+            Expr::parse("'Q1 ''24'!B2:A1")?,
+            Expr::SheetRef(
+                "Q1 '24".to_string(),
+                Box::new(Expr::Range(rel(0, 0), rel(1, 1)))
+            )
+        );
+        let round_trip = |s: &str| Expr::parse(s).map(|e| e.to_string());
+        assert_eq!(round_trip("'Q1 ''24'!A1:B2+A1")?, "'Q1 ''24'!A1:B2+A1");
+        assert_eq!(round_trip("SUM( sheet2 ! A1 )")?, "SUM(sheet2!A1)");
+        assert!(Expr::parse("Data!SUM(A1)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_names() -> TR {
+        assert_eq!(
+            Expr::parse("TaxRate * Subtotal")?,
+            Expr::BinOp(
+                Op::Mul,
+                Box::new(Expr::Name("TaxRate".to_string())),
+                Box::new(Expr::Name("Subtotal".to_string()))
             )
         );
+        assert_eq!(Expr::parse("SUM(items)")?.names(), ["items"]);
+        assert!(Expr::parse("$B$7").is_ok());
+        assert!(Expr::parse("B0").is_err());
         Ok(())
     }
 
@@ -404,10 +2277,142 @@ mod expr_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_fn_call_one_arg() -> TR {
+        let e = Expr::parse("foo(1)")?;
+        assert_eq!(e, Expr::FnCall("foo".to_string(), vec![Expr::Int(1)]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_a1_ref() -> TR {
+        assert_eq!(Expr::parse("A1")?, Expr::CellRef(rel(0, 0)));
+        assert_eq!(Expr::parse("b12")?, Expr::CellRef(rel(1, 11)));
+        assert_eq!(
+            Expr::parse("(AA3+Ref(1,2))")?,
+            Expr::BinOp(
+                Op::Add,
+                Box::new(Expr::CellRef(rel(26, 2))),
+                Box::new(Expr::CellRef(Reference::absolute(1, 2)))
+            )
+        );
+        assert!(Expr::parse("A0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_range() -> TR {
+        let range = Expr::Range(rel(0, 0), rel(1, 2));
+        assert_eq!(Expr::parse("A1:B3")?, range);
+        assert_eq!(Expr::parse("B3:A1")?, range);
+        assert_eq!(
+            Expr::parse("Ref(1,1):C5")?,
+            Expr::Range(Reference::absolute(1, 1), rel(2, 4))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_absolute_refs() -> TR {
+        let r = |x, y, abs_x, abs_y| Reference { x, y, abs_x, abs_y };
+        assert_eq!(Expr::parse("$A$1")?, Expr::CellRef(r(0, 0, true, true)));
+        assert_eq!(Expr::parse("$B3")?, Expr::CellRef(r(1, 2, true, false)));
+        assert_eq!(Expr::parse("B$3")?, Expr::CellRef(r(1, 2, false, true)));
+        assert_eq!(
+            Expr::parse("C$1:$A2")?,
+            Expr::Range(r(0, 0, true, true), r(2, 1, false, false))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize() -> TR {
+        for s in [
+            "52",
+            "true",
+            "1+(2=A1)",
+            "1-(2-3)*-A1^2",
+            "1<>2>=(3<=4)",
+            "-(1+2)%4/5",
+            "\"a \"\"b\"\"\"&1+2",
+            "(\"a\"&1)+2",
+            "if(true,$B$2,SUM(A1:C$3,4))",
+            "ISFORMULA($A1)",
+            "1.5*-2.0+1e20-2.5e-7",
+            "IFERROR(#DIV/0!,#N/A)",
+        ] {
+            assert_eq!(Expr::parse(s)?.to_string(), s);
+        }
+        assert_eq!(Expr::parse("Ref(1,2)")?.to_string(), "$B$3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_whitespace() -> TR {
+        assert_eq!(Expr::parse("( 1 + 2 )")?, Expr::parse("(1+2)")?);
+        assert_eq!(
+            Expr::parse(" SUM( A1 : $B$2 , 3 )  ")?,
+            Expr::parse("SUM(A1:$B$2,3)")?
+        );
+        assert_eq!(
+            Expr::parse("if( true ,Ref( 1 , 2 ), - 3)")?,
+            Expr::parse("if(true,Ref(1,2),-3)")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error_column() {
+        let col = |s| match Expr::parse(s) {
+            Err(Error::ParseError(col, _)) => col,
+            _ => panic!("expected a parse error for {}", s),
+        };
+        assert_eq!(col("1 +"), 4);
+        assert_eq!(col("1 + )"), 5);
+        assert_eq!(col("SUM(1, 2 3)"), 10);
+        assert_eq!(col("1 ~ 2"), 3);
+    }
+
+    #[test]
+    fn test_parse_fn_call_no_args() -> TR {
+        let e = Expr::parse("foo()")?;
+        assert_eq!(e, Expr::FnCall("foo".to_string(), vec![]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_string() -> TR {
+        assert_eq!(
+            Expr::parse("\"hi there\"")?,
+            Expr::String("hi there".to_string())
+        );
+        assert_eq!(
+            Expr::parse("\"say \"\"hi\"\"\"")?,
+            Expr::String("say \"hi\"".to_string())
+        );
+        assert_eq!(Expr::parse("\"\"")?, Expr::String("".to_string()));
+        assert!(Expr::parse("\"oops").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concat() -> TR {
+        let s = Spreadsheet::new();
+        let eval = |f: &str| Expr::parse(f).and_then(|e| e.eval(&s));
+        assert_eq!(eval("\"a\"&\"b\"")?, Value::String("ab".to_string()));
+        assert_eq!(eval("\"n=\"&1+2")?, Value::String("n=3".to_string()));
+        assert_eq!(eval("1&2=\"12\"")?, Value::Bool(true));
+        assert_eq!(eval("\"A\"=\"a\"")?, Value::Bool(true));
+        Ok(())
+    }
+
     #[test]
     fn test_parse_eq() -> TR {
         let e = Expr::parse("(1=2)")?;
-        assert_eq!(e, Expr::Eq(Box::new(Expr::Int(1)), Box::new(Expr::Int(2))));
+        assert_eq!(
+            e,
+            Expr::BinOp(Op::Eq, Box::new(Expr::Int(1)), Box::new(Expr::Int(2)))
+        );
         Ok(())
     }
 
@@ -425,6 +2430,96 @@ mod expr_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_precedence() -> TR {
+        let bin = |op, x, y| Expr::BinOp(op, Box::new(x), Box::new(y));
+        assert_eq!(
+            Expr::parse("1+2*3")?,
+            bin(
+                Op::Add,
+                Expr::Int(1),
+                bin(Op::Mul, Expr::Int(2), Expr::Int(3))
+            )
+        );
+        assert_eq!(
+            Expr::parse("1-2-3")?,
+            bin(
+                Op::Sub,
+                bin(Op::Sub, Expr::Int(1), Expr::Int(2)),
+                Expr::Int(3)
+            )
+        );
+        assert_eq!(
+            Expr::parse("1+2<=3")?,
+            bin(
+                Op::Le,
+                bin(Op::Add, Expr::Int(1), Expr::Int(2)),
+                Expr::Int(3)
+            )
+        );
+        assert_eq!(
+            Expr::parse("-2^2")?,
+            bin(Op::Pow, Expr::Int(-2), Expr::Int(2))
+        );
+        assert_eq!(
+            Expr::parse("-(2)^2")?,
+            bin(Op::Pow, Expr::Neg(Box::new(Expr::Int(2))), Expr::Int(2))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_arithmetic() -> TR {
+        let s = Spreadsheet::new();
+        let eval = |f: &str| Expr::parse(f).and_then(|e| e.eval(&s));
+        assert_eq!(eval("1+2*3")?, Value::Int(7));
+        assert_eq!(eval("(1+2)*3")?, Value::Int(9));
+        assert_eq!(eval("10-4-3")?, Value::Int(3));
+        assert_eq!(eval("8/2")?, Value::Int(4));
+        assert_eq!(eval("7/2")?, Value::Float(3.5));
+        assert_eq!(eval("2^3^2")?, Value::Int(64));
+        assert_eq!(eval("-2^2")?, Value::Int(4));
+        assert_eq!(eval("7%3")?, Value::Int(1));
+        assert_eq!(eval("-7%3")?, Value::Int(2));
+        assert_eq!(eval("7%-3")?, Value::Int(-2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparisons() -> TR {
+        let s = Spreadsheet::new();
+        let eval = |f: &str| Expr::parse(f).and_then(|e| e.eval(&s));
+        assert_eq!(eval("1<2")?, Value::Bool(true));
+        assert_eq!(eval("2<=2")?, Value::Bool(true));
+        assert_eq!(eval("1>2")?, Value::Bool(false));
+        assert_eq!(eval("3>=4")?, Value::Bool(false));
+        assert_eq!(eval("1<>2")?, Value::Bool(true));
+        assert_eq!(eval("1+1=2")?, Value::Bool(true));
+        assert_eq!(eval("false<true")?, Value::Bool(true));
+        assert!(eval("1<true").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        let s = Spreadsheet::new();
+        let eval = |f: &str| Expr::parse(f).and_then(|e| e.eval(&s));
+        assert!(matches!(eval("1/0"), Err(Error::DivByZero)));
+        assert!(matches!(eval("1+#NUM!"), Err(Error::Overflow)));
+        assert!(matches!(eval("#N/A=1/0"), Err(Error::NotAvailable)));
+        assert!(Expr::parse("#OOPS!").is_err());
+        assert!(matches!(eval("1%0"), Err(Error::DivByZero)));
+        assert!(matches!(
+            eval("9223372036854775807+1"),
+            Err(Error::Overflow)
+        ));
+        assert!(matches!(eval("2^64"), Err(Error::Overflow)));
+        assert!(matches!(eval("1.5/0"), Err(Error::DivByZero)));
+        assert!(matches!(eval("0^-1"), Err(Error::DivByZero)));
+        assert!(matches!(eval("10.0^400"), Err(Error::Overflow)));
+        assert!(matches!(eval("(-8)^0.5"), Err(Error::DescriptiveError(_))));
+    }
+
     #[test]
     fn test_addition() -> TR {
         let s = Spreadsheet::new();
@@ -0,0 +1,231 @@
+// Reading and writing sheets as CSV, following RFC 4180.
+//
+// Fields holding the delimiter, a quote or a line break are quoted, with
+// quotes inside doubled. Lines are written with "\r\n" endings, and may end
+// with either "\r\n" or "\n" when read.
+
+use super::{CellRef, Error, Spreadsheet, Value};
+
+pub struct CsvOptions {
+    pub delimiter: char,
+    // Whether to write what formulas evaluate to rather than the formulas.
+    pub values: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        CsvOptions {
+            delimiter: ',',
+            values: false,
+        }
+    }
+}
+
+impl Spreadsheet {
+    // Every cell from A1 to the sheet's extent is written, empty or not.
+    pub fn to_csv(&self, options: &CsvOptions) -> String {
+        if self.cells.is_empty() {
+            return String::new();
+        }
+        let (w, h) = self.get_max_dims();
+        let mut out = String::new();
+        for y in 0..=h {
+            let fields: Vec<String> = (0..=w)
+                .map(|x| {
+                    let text = match options.values {
+                        true => self.value_text(x, y),
+                        false => self.raw_cell(x, y),
+                    };
+                    quote(&text, options.delimiter)
+                })
+                .collect();
+            out.push_str(&fields.join(&options.delimiter.to_string()));
+            out.push_str("\r\n");
+        }
+        out
+    }
+
+    // Fields become the raw contents of cells, so formulas and typed values
+    // are read just as if they had been entered by hand.
+    pub fn from_csv(text: &str, delimiter: char) -> Result<Spreadsheet, Error> {
+        let mut cells = vec![];
+        for (y, row) in parse(text, delimiter)?.into_iter().enumerate() {
+            for (x, field) in row.into_iter().enumerate() {
+                cells.push((CellRef(x, y), field));
+            }
+        }
+        let mut sheet = Spreadsheet::new();
+        sheet.fill(cells);
+        Ok(sheet)
+    }
+
+    // Like `show_cell`, but without quotes around text from formulas.
+    fn value_text(&self, x: usize, y: usize) -> String {
+        match self.cell(x, y) {
+            Some(c) if c.contents.starts_with('=') => match &c.value {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            },
+            _ => self.show_cell(x, y),
+        }
+    }
+}
+
+fn quote(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Splits CSV text into rows of fields. A quote in the middle of an unquoted
+// field is kept as it is, since plenty of writers produce them.
+fn parse(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, Error> {
+    let error = |line: usize, msg: &str| Error::DescriptiveError(format!("line {}: {}", line, msg));
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut line = 1;
+    let mut at_field_start = true;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if at_field_start && c == '"' {
+            let start_line = line;
+            loop {
+                match chars.next() {
+                    None => return Err(error(start_line, "unterminated quoted field")),
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => {
+                        if c == '\n' {
+                            line += 1;
+                        }
+                        field.push(c);
+                    }
+                }
+            }
+            at_field_start = false;
+            match chars.peek() {
+                None | Some('\r') | Some('\n') => (),
+                Some(c) if *c == delimiter => (),
+                Some(_) => return Err(error(line, "expected a delimiter after a quoted field")),
+            }
+            continue;
+        }
+
+        at_field_start = false;
+        match c {
+            c if c == delimiter => {
+                row.push(std::mem::take(&mut field));
+                at_field_start = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                line += 1;
+                at_field_start = true;
+            }
+            c => field.push(c),
+        }
+    }
+    // The last line needn't end with a line break.
+    if !at_field_start || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TR = Result<(), Error>;
+
+    fn fields(row: &[&str]) -> Vec<String> {
+        row.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() -> TR {
+        assert_eq!(
+            parse("a,b\r\n1,,3\n", ',')?,
+            vec![fields(&["a", "b"]), fields(&["1", "", "3"])]
+        );
+        assert_eq!(parse("x,", ',')?, vec![fields(&["x", ""])]);
+        assert_eq!(parse("", ',')?, Vec::<Vec<String>>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_quoted() -> TR {
+        assert_eq!(
+            parse("\"a,b\",\"say \"\"hi\"\"\"\n\"two\r\nlines\",\"\"", ',')?,
+            vec![
+                fields(&["a,b", "say \"hi\""]),
+                fields(&["two\r\nlines", ""])
+            ]
+        );
+        assert_eq!(parse("5\"6,7", ',')?, vec![fields(&["5\"6", "7"])]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse("a\nb,\"c\nd", ',').unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unterminated quoted field");
+        let err = parse("a\n\"b\"c", ',').unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: expected a delimiter after a quoted field"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() -> TR {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "name, first".to_string());
+        sheet.set(1, 0, "5".to_string());
+        sheet.set(2, 1, "=SUM(B1,3)&\"!\"".to_string());
+        sheet.set(0, 2, "'007".to_string());
+        let csv = sheet.to_csv(&CsvOptions::default());
+        assert_eq!(
+            csv,
+            "\"name, first\",5,\r\n,,\"=SUM(B1,3)&\"\"!\"\"\"\r\n'007,,\r\n"
+        );
+
+        let loaded = Spreadsheet::from_csv(&csv, ',')?;
+        assert_eq!(loaded.get_max_dims(), (2, 2));
+        for y in 0..3 {
+            for x in 0..3 {
+                assert_eq!(loaded.raw_cell(x, y), sheet.raw_cell(x, y));
+            }
+        }
+        assert_eq!(loaded.show_cell(2, 1), "\"8!\"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_values() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "2".to_string());
+        sheet.set(1, 0, "=A1/4".to_string());
+        sheet.set(2, 0, "=\"a\"&A1".to_string());
+        sheet.set(0, 1, "=1/0".to_string());
+        let options = CsvOptions {
+            delimiter: '\t',
+            values: true,
+        };
+        assert_eq!(sheet.to_csv(&options), "2\t0.5\ta2\r\n#DIV/0!\t\t\r\n");
+    }
+
+    #[test]
+    fn test_empty_sheet() {
+        assert_eq!(Spreadsheet::new().to_csv(&CsvOptions::default()), "");
+    }
+}
@@ -0,0 +1,620 @@
+// The standard library of functions callable from formulas.
+//
+// Names are stored upper case; lookups are case-insensitive.
+
+use super::{Error, Expr, Spreadsheet, Value};
+use lazy_static::lazy_static;
+use maplit::*;
+use std::collections::HashMap;
+
+// Built-ins receive their arguments unevaluated so that they can decide what
+// to evaluate (like `if` does), and can read from the sheet.
+pub type BuiltIn = fn(Args) -> Result<Value, Error>;
+
+lazy_static! {
+    static ref BUILT_INS: HashMap<String, BuiltIn> = convert_args!(hashmap!(
+        "SUM" => sum as BuiltIn,
+        "MIN" => min as BuiltIn,
+        "MAX" => max as BuiltIn,
+        "AVERAGE" => average as BuiltIn,
+        "COUNT" => count as BuiltIn,
+        "AND" => and as BuiltIn,
+        "OR" => or as BuiltIn,
+        "ISFORMULA" => is_formula as BuiltIn,
+        "ISERROR" => is_error as BuiltIn,
+        "ISBLANK" => is_blank as BuiltIn,
+        "IFERROR" => if_error as BuiltIn,
+        "NA" => na as BuiltIn,
+        "LEN" => len as BuiltIn,
+        "UPPER" => upper as BuiltIn,
+        "LOWER" => lower as BuiltIn,
+        "LEFT" => left as BuiltIn,
+        "RIGHT" => right as BuiltIn,
+        "MID" => mid as BuiltIn,
+        "TRIM" => trim as BuiltIn,
+        "FIND" => find as BuiltIn,
+        "SUBSTITUTE" => substitute as BuiltIn,
+    ));
+}
+
+pub(super) fn lookup(name: &str) -> Option<&'static BuiltIn> {
+    BUILT_INS.get(&name.to_ascii_uppercase())
+}
+
+pub struct Args<'a> {
+    name: &'a str,
+    exprs: &'a [Expr],
+    sheet: &'a Spreadsheet,
+}
+
+impl<'a> Args<'a> {
+    pub(super) fn new(name: &'a str, exprs: &'a [Expr], sheet: &'a Spreadsheet) -> Args<'a> {
+        Args { name, exprs, sheet }
+    }
+
+    fn len(&self) -> usize {
+        self.exprs.len()
+    }
+
+    fn expr(&self, i: usize) -> &'a Expr {
+        &self.exprs[i]
+    }
+
+    fn eval(&self, i: usize) -> Result<Value, Error> {
+        self.exprs[i].eval(self.sheet)
+    }
+
+    // Like `eval`, but a range gives only the values of its filled cells, as
+    // a single row. This is for functions which skip blanks anyway, so that
+    // they don't depend on the size of the range.
+    fn eval_filled(&self, i: usize) -> Result<Value, Error> {
+        match self.exprs[i].filled_values(self.sheet) {
+            Some(values) => Ok(Value::Array(vec![values])),
+            None => self.eval(i),
+        }
+    }
+
+    fn eval_all(&self) -> Result<Vec<Value>, Error> {
+        (0..self.len()).map(|i| self.eval_filled(i)).collect()
+    }
+
+    fn text(&self, i: usize) -> Result<String, Error> {
+        self.eval(i)?.as_text()
+    }
+
+    // Evaluates a non-negative whole number argument, using `default` if the
+    // argument was left out.
+    fn count(&self, i: usize, default: usize) -> Result<usize, Error> {
+        if i >= self.len() {
+            return Ok(default);
+        }
+        let x = match self.eval(i)? {
+            Value::Int(x) => x,
+            // Fractions are dropped, as in other spreadsheets.
+            Value::Float(x) => x.trunc() as i64,
+            Value::Blank => 0,
+            _ => return Err(Error::TypeError),
+        };
+        match x {
+            x if x >= 0 => Ok(x as usize),
+            x => Err(Error::DescriptiveError(format!(
+                "{} expects argument {} to be at least 0, got {}",
+                self.name.to_ascii_uppercase(),
+                i + 1,
+                x
+            ))),
+        }
+    }
+
+    // Like `count`, but for one-based positions.
+    fn position(&self, i: usize, default: usize) -> Result<usize, Error> {
+        match self.count(i, default)? {
+            0 => Err(Error::DescriptiveError(format!(
+                "{} expects argument {} to be at least 1, got 0",
+                self.name.to_ascii_uppercase(),
+                i + 1
+            ))),
+            n => Ok(n),
+        }
+    }
+
+    fn check_arity(&self, min: usize, max: Option<usize>) -> Result<(), Error> {
+        let n = self.len();
+        let expected = match max {
+            _ if n < min => format!("at least {}", min),
+            Some(max) if n > max => format!("at most {}", max),
+            _ => return Ok(()),
+        };
+        Err(Error::DescriptiveError(format!(
+            "{} expects {} argument(s), got {}",
+            self.name.to_ascii_uppercase(),
+            expected,
+            n
+        )))
+    }
+}
+
+// Returns the numeric arguments, rejecting any that aren't numbers. Arrays
+// contribute their numeric elements and skip everything else, so that a
+// range can include labels and blanks, but an error in one is passed on.
+// Blanks are skipped wherever they are.
+fn numbers(args: &[Value]) -> Result<Vec<Value>, Error> {
+    let mut xs = vec![];
+    for arg in args {
+        match arg {
+            Value::Int(_) | Value::Float(_) => xs.push(arg.clone()),
+            Value::Blank => (),
+            Value::Array(rows) => {
+                for v in rows.iter().flatten() {
+                    match v {
+                        Value::Int(_) | Value::Float(_) => xs.push(v.clone()),
+                        Value::Error(e) => return Err(e.clone()),
+                        _ => (),
+                    }
+                }
+            }
+            _ => return Err(Error::TypeError),
+        }
+    }
+    Ok(xs)
+}
+
+// The sum stays an integer unless one of the numbers is a float.
+fn checked_sum(xs: &[Value]) -> Result<Value, Error> {
+    let mut total = Value::Int(0);
+    for x in xs {
+        total = match (total, x) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(a.checked_add(*b).ok_or(Error::Overflow)?),
+            (a, b) => Value::Float(a.as_f64().unwrap() + b.as_f64().unwrap()),
+        };
+    }
+    Ok(total)
+}
+
+fn sum(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, None)?;
+    checked_sum(&numbers(&args.eval_all()?)?)
+}
+
+fn min(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, None)?;
+    let xs = numbers(&args.eval_all()?)?;
+    Ok(xs
+        .into_iter()
+        .min_by(|a, b| a.as_f64().unwrap().total_cmp(&b.as_f64().unwrap()))
+        .unwrap_or(Value::Int(0)))
+}
+
+fn max(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, None)?;
+    let xs = numbers(&args.eval_all()?)?;
+    Ok(xs
+        .into_iter()
+        .max_by(|a, b| a.as_f64().unwrap().total_cmp(&b.as_f64().unwrap()))
+        .unwrap_or(Value::Int(0)))
+}
+
+fn average(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, None)?;
+    let xs = numbers(&args.eval_all()?)?;
+    if xs.is_empty() {
+        return Err(Error::DivByZero);
+    }
+    let total: f64 = xs.iter().map(|x| x.as_f64().unwrap()).sum();
+    Ok(Value::Float(total / xs.len() as f64))
+}
+
+// Counts the arguments that are numbers, ignoring everything else, errors
+// included.
+fn count(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, None)?;
+    let vals: Vec<Value> = (0..args.len())
+        .filter_map(|i| args.eval_filled(i).ok())
+        .collect();
+    let n = vals
+        .iter()
+        .flat_map(|v| match v {
+            Value::Array(rows) => rows.iter().flatten().collect(),
+            v => vec![v],
+        })
+        .filter(|v| matches!(v, Value::Int(_) | Value::Float(_)))
+        .count();
+    Ok(Value::Int(n as i64))
+}
+
+// Stops evaluating at the first false argument.
+fn and(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, None)?;
+    for i in 0..args.len() {
+        match args.eval(i)? {
+            Value::Bool(true) => (),
+            Value::Bool(false) => return Ok(Value::Bool(false)),
+            _ => return Err(Error::TypeError),
+        }
+    }
+    Ok(Value::Bool(true))
+}
+
+// Stops evaluating at the first true argument.
+fn or(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, None)?;
+    for i in 0..args.len() {
+        match args.eval(i)? {
+            Value::Bool(false) => (),
+            Value::Bool(true) => return Ok(Value::Bool(true)),
+            _ => return Err(Error::TypeError),
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+// Whether the referenced cell holds a formula rather than plain data.
+fn is_formula(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(1))?;
+    match args.expr(0) {
+        Expr::CellRef(r) => Ok(Value::Bool(args.sheet.raw_cell(r.x, r.y).starts_with('='))),
+        // Like a reference to a deleted cell.
+        Expr::Error(e) => Err(e.clone()),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/***** Error handling. *****/
+
+// Whether the argument is an error, element by element for an array.
+fn is_error(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(1))?;
+    match args.eval(0) {
+        Err(_) => Ok(Value::Bool(true)),
+        Ok(v) => v.map(|v| Ok(Value::Bool(matches!(v, Value::Error(_))))),
+    }
+}
+
+// Whether the argument is an empty cell, element by element for an array.
+// Text, even "", isn't blank.
+fn is_blank(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(1))?;
+    args.eval(0)?.map(|v| Ok(Value::Bool(v == Value::Blank)))
+}
+
+// IFERROR(value, fallback) gives `fallback` in place of an error, element by
+// element for an array. `fallback` is only evaluated if it's needed.
+fn if_error(args: Args) -> Result<Value, Error> {
+    args.check_arity(2, Some(2))?;
+    match args.eval(0) {
+        Err(_) => args.eval(1),
+        Ok(v) => v.map(|v| match v {
+            Value::Error(_) => args.eval(1),
+            v => Ok(v),
+        }),
+    }
+}
+
+// Marks a value as missing.
+fn na(args: Args) -> Result<Value, Error> {
+    args.check_arity(0, Some(0))?;
+    Err(Error::NotAvailable)
+}
+
+/***** Text functions. Lengths and positions count characters, not bytes. *****/
+
+fn len(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(1))?;
+    Ok(Value::Int(args.text(0)?.chars().count() as i64))
+}
+
+fn upper(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(1))?;
+    Ok(Value::String(args.text(0)?.to_uppercase()))
+}
+
+fn lower(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(1))?;
+    Ok(Value::String(args.text(0)?.to_lowercase()))
+}
+
+fn left(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(2))?;
+    let n = args.count(1, 1)?;
+    Ok(Value::String(args.text(0)?.chars().take(n).collect()))
+}
+
+fn right(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(2))?;
+    let s = args.text(0)?;
+    let n = args.count(1, 1)?;
+    let skip = s.chars().count().saturating_sub(n);
+    Ok(Value::String(s.chars().skip(skip).collect()))
+}
+
+fn mid(args: Args) -> Result<Value, Error> {
+    args.check_arity(3, Some(3))?;
+    let start = args.position(1, 1)?;
+    let n = args.count(2, 0)?;
+    let s = args.text(0)?.chars().skip(start - 1).take(n).collect();
+    Ok(Value::String(s))
+}
+
+// Drops leading and trailing spaces, and shortens runs of spaces to one.
+fn trim(args: Args) -> Result<Value, Error> {
+    args.check_arity(1, Some(1))?;
+    let s = args.text(0)?;
+    let words: Vec<&str> = s.split(' ').filter(|w| !w.is_empty()).collect();
+    Ok(Value::String(words.join(" ")))
+}
+
+// FIND(needle, haystack, [start]) returns the position of the first match at
+// or after `start`. The search is case-sensitive.
+fn find(args: Args) -> Result<Value, Error> {
+    args.check_arity(2, Some(3))?;
+    let needle = args.text(0)?;
+    let haystack: Vec<char> = args.text(1)?.chars().collect();
+    let start = args.position(2, 1)?;
+    let needle: Vec<char> = needle.chars().collect();
+    if start > haystack.len() + 1 {
+        return Err(Error::DescriptiveError(format!(
+            "FIND start position {} is past the end of the text",
+            start
+        )));
+    }
+    (start - 1..=haystack.len())
+        .find(|i| haystack[*i..].starts_with(&needle))
+        .map(|i| Value::Int(i as i64 + 1))
+        .ok_or_else(|| {
+            let needle: String = needle.iter().collect();
+            Error::DescriptiveError(format!("FIND couldn't find \"{}\"", needle))
+        })
+}
+
+// SUBSTITUTE(text, old, new, [instance]) replaces every occurrence of `old`,
+// or only the given (one-based) occurrence.
+fn substitute(args: Args) -> Result<Value, Error> {
+    args.check_arity(3, Some(4))?;
+    let text = args.text(0)?;
+    let old = args.text(1)?;
+    let new = args.text(2)?;
+    if old.is_empty() {
+        return Ok(Value::String(text));
+    }
+    if args.len() < 4 {
+        return Ok(Value::String(text.replace(&old, &new)));
+    }
+    let instance = args.position(3, 1)?;
+    match text.match_indices(&old).nth(instance - 1) {
+        Some((i, _)) => Ok(Value::String(format!(
+            "{}{}{}",
+            &text[..i],
+            new,
+            &text[i + old.len()..]
+        ))),
+        None => Ok(Value::String(text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Expr, Spreadsheet};
+    use super::*;
+
+    type TR = Result<(), Error>;
+
+    fn eval(s: &str) -> Result<Value, Error> {
+        Expr::parse(s)?.eval(&Spreadsheet::new())
+    }
+
+    #[test]
+    fn test_arity() {
+        let err = eval("ISFORMULA(Ref(0,0),Ref(0,1))").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ISFORMULA expects at most 1 argument(s), got 2"
+        );
+    }
+
+    #[test]
+    fn test_sum() -> TR {
+        assert_eq!(eval("SUM(1,2,3)")?, Value::Int(6));
+        assert_eq!(eval("sum(4)")?, Value::Int(4));
+        assert_eq!(eval("SUM(1,2.5)")?, Value::Float(3.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_max() -> TR {
+        assert_eq!(eval("MIN(5,2,(1+7))")?, Value::Int(2));
+        assert_eq!(eval("MAX(5,2,(1+7))")?, Value::Int(8));
+        assert_eq!(eval("MIN(5,-2.5,3)")?, Value::Float(-2.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_average() -> TR {
+        assert_eq!(eval("AVERAGE(2,4,9)")?, Value::Float(5.0));
+        assert_eq!(eval("AVERAGE(1,2)")?, Value::Float(1.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_count() -> TR {
+        assert_eq!(eval("COUNT(1,true,3.5)")?, Value::Int(2));
+        assert_eq!(
+            eval("COUNT()").unwrap_err().to_string(),
+            "COUNT expects at least 1 argument(s), got 0"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=1".to_string());
+        sheet.set(0, 1, "=2".to_string());
+        sheet.set(0, 2, "label".to_string());
+        sheet.set(1, 0, "=3".to_string());
+        sheet.set(1, 1, "=4".to_string());
+        sheet.set(2, 0, "=SUM(A1:B3,10)".to_string());
+        sheet.set(2, 1, "=MAX(A1:A3)".to_string());
+        sheet.set(2, 2, "=COUNT(A1:B3)".to_string());
+        sheet.set(2, 3, "=AVERAGE(B1:B2)".to_string());
+        assert_eq!(sheet.show_cell(2, 0), "20");
+        assert_eq!(sheet.show_cell(2, 1), "2");
+        assert_eq!(sheet.show_cell(2, 2), "4");
+        assert_eq!(sheet.show_cell(2, 3), "3.5");
+
+        sheet.set(1, 1, "=40".to_string());
+        assert_eq!(sheet.show_cell(2, 0), "56");
+    }
+
+    #[test]
+    fn test_and_or() -> TR {
+        assert_eq!(eval("AND(true,true)")?, Value::Bool(true));
+        assert_eq!(eval("AND(true,false)")?, Value::Bool(false));
+        assert_eq!(eval("OR(false,true)")?, Value::Bool(true));
+        assert_eq!(eval("OR(false,false)")?, Value::Bool(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_and_or_short_circuit() -> TR {
+        assert_eq!(eval("AND(false,(1=true))")?, Value::Bool(false));
+        assert_eq!(eval("OR(true,(1=true))")?, Value::Bool(true));
+        assert!(eval("AND(true,(1=true))").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_formula() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=1".to_string());
+        sheet.set(1, 0, "1".to_string());
+        sheet.set(0, 1, "=ISFORMULA(Ref(0,0))".to_string());
+        sheet.set(1, 1, "=ISFORMULA(Ref(1,0))".to_string());
+        assert_eq!(sheet.show_cell(0, 1), "true");
+        assert_eq!(sheet.show_cell(1, 1), "false");
+
+        sheet.set(1, 0, "=2".to_string());
+        assert_eq!(sheet.show_cell(1, 1), "true");
+    }
+
+    fn text(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_len_upper_lower() -> TR {
+        assert_eq!(eval("LEN(\"héllo\")")?, Value::Int(5));
+        assert_eq!(eval("LEN(1234)")?, Value::Int(4));
+        assert_eq!(eval("UPPER(\"MiXed\")")?, text("MIXED"));
+        assert_eq!(eval("LOWER(\"MiXed\")")?, text("mixed"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_left_right_mid() -> TR {
+        assert_eq!(eval("LEFT(\"spreadsheet\")")?, text("s"));
+        assert_eq!(eval("LEFT(\"spreadsheet\",6)")?, text("spread"));
+        assert_eq!(eval("LEFT(\"spreadsheet\",2.9)")?, text("sp"));
+        assert_eq!(eval("RIGHT(\"spreadsheet\",5)")?, text("sheet"));
+        assert_eq!(eval("RIGHT(\"ab\",5)")?, text("ab"));
+        assert_eq!(eval("MID(\"spreadsheet\",3,4)")?, text("read"));
+        assert_eq!(eval("MID(\"abc\",5,1)")?, text(""));
+        assert!(eval("LEFT(\"abc\",-1)").is_err());
+        assert!(eval("MID(\"abc\",0,1)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim() -> TR {
+        assert_eq!(eval("TRIM(\"  a   b c  \")")?, text("a b c"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find() -> TR {
+        assert_eq!(eval("FIND(\"b\",\"abcabc\")")?, Value::Int(2));
+        assert_eq!(eval("FIND(\"b\",\"abcabc\",3)")?, Value::Int(5));
+        assert_eq!(eval("FIND(\"\",\"abc\")")?, Value::Int(1));
+        assert!(eval("FIND(\"B\",\"abc\")").is_err());
+        assert!(eval("FIND(\"a\",\"abc\",5)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_substitute() -> TR {
+        assert_eq!(eval("SUBSTITUTE(\"a-b-c\",\"-\",\"+\")")?, text("a+b+c"));
+        assert_eq!(eval("SUBSTITUTE(\"a-b-c\",\"-\",\"\",2)")?, text("a-bc"));
+        assert_eq!(eval("SUBSTITUTE(\"a-b-c\",\"-\",\"+\",3)")?, text("a-b-c"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_errors() {
+        assert!(matches!(eval("SUM(1,true)"), Err(Error::TypeError)));
+        assert!(matches!(eval("MAX(false)"), Err(Error::TypeError)));
+    }
+
+    #[test]
+    fn test_overflow() {
+        // The same error as adding with `+`.
+        assert_eq!(eval("SUM(9223372036854775807,1)"), Err(Error::Overflow));
+        assert_eq!(eval("9223372036854775807+1"), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        assert_eq!(eval("NOPE(1)"), Err(Error::NameError("NOPE".to_string())));
+    }
+
+    #[test]
+    fn test_is_error() -> TR {
+        assert_eq!(eval("ISERROR(1/0)")?, Value::Bool(true));
+        assert_eq!(eval("ISERROR(#N/A)")?, Value::Bool(true));
+        assert_eq!(eval("ISERROR(NA())")?, Value::Bool(true));
+        assert_eq!(eval("ISERROR(1)")?, Value::Bool(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_error() -> TR {
+        assert_eq!(eval("IFERROR(1/0,7)")?, Value::Int(7));
+        assert_eq!(eval("IFERROR(NOPE(),\"x\")")?, text("x"));
+        assert_eq!(eval("IFERROR(3,1/0)")?, Value::Int(3));
+        assert_eq!(eval("IFERROR(1/0,#REF!)"), Err(Error::RefError));
+        Ok(())
+    }
+
+    #[test]
+    fn test_blanks() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "2".to_string());
+        sheet.set(0, 2, "4".to_string());
+        sheet.set(1, 0, "=AVERAGE(A1:A3)".to_string());
+        sheet.set(1, 1, "=COUNT(A1:A3)".to_string());
+        sheet.set(1, 2, "=SUM(A2,A3)".to_string());
+        sheet.set(1, 3, "=ISBLANK(A1:A3)".to_string());
+        sheet.set(1, 4, "=ISBLANK(\"\")".to_string());
+        sheet.set(1, 5, "=LEN(A2)".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "3");
+        assert_eq!(sheet.show_cell(1, 1), "2");
+        assert_eq!(sheet.show_cell(1, 2), "4");
+        assert_eq!(sheet.show_cell(1, 3), "{false;true;false}");
+        assert_eq!(sheet.show_cell(1, 4), "false");
+        assert_eq!(sheet.show_cell(1, 5), "0");
+    }
+
+    #[test]
+    fn test_errors_in_ranges() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "1".to_string());
+        sheet.set(0, 1, "=1/0".to_string());
+        sheet.set(0, 2, "3".to_string());
+        sheet.set(1, 0, "=SUM(A1:A3)".to_string());
+        sheet.set(1, 1, "=COUNT(A1:A3)".to_string());
+        sheet.set(1, 2, "=ISERROR(A1:A3)".to_string());
+        sheet.set(1, 3, "=IFERROR(A1:A3*2,0)".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "#DIV/0!");
+        assert_eq!(sheet.show_cell(1, 1), "2");
+        assert_eq!(sheet.show_cell(1, 2), "{false;true;false}");
+        assert_eq!(sheet.show_cell(1, 3), "{2;0;6}");
+    }
+}
@@ -0,0 +1,185 @@
+// Undoing and redoing edits to a sheet.
+//
+// Every change to a sheet's contents is recorded as a command holding what's
+// needed to make it again or take it back. Commands are grouped into steps,
+// so that clearing a range or deleting rows is undone all at once. Making a
+// new edit after undoing some forgets the steps that could have been redone,
+// and only the latest steps are kept, so the history doesn't grow forever.
+
+use super::{CellRef, Edit, Spreadsheet};
+
+#[derive(Debug)]
+pub(super) enum Command {
+    // A cell's contents before and after.
+    Set(CellRef, String, String),
+    // A structural edit, along with the cells it deleted or changed other
+    // than by moving, where they were before it and with their contents then.
+    Restructure(Edit, Vec<(CellRef, String)>),
+}
+
+// How many steps can be undone.
+pub(super) const MAX_STEPS: usize = 100;
+
+#[derive(Default)]
+pub(super) struct History {
+    done: Vec<Vec<Command>>,
+    undone: Vec<Vec<Command>>,
+}
+
+impl Spreadsheet {
+    // Takes back the latest step which hasn't been undone, returning whether
+    // there was one.
+    pub fn undo(&mut self) -> bool {
+        self.undo_step().is_some()
+    }
+
+    // Makes the latest undone step again, returning whether there was one.
+    pub fn redo(&mut self) -> bool {
+        self.redo_step().is_some()
+    }
+
+    // Like `undo`, but returning the cells it changed.
+    pub(super) fn undo_step(&mut self) -> Option<Vec<CellRef>> {
+        let step = self.history.done.pop()?;
+        let mut changed = vec![];
+        for command in step.iter().rev() {
+            changed.extend(self.run(command, true));
+        }
+        self.history.undone.push(step);
+        Some(changed)
+    }
+
+    // Like `redo`, but returning the cells it changed.
+    pub(super) fn redo_step(&mut self) -> Option<Vec<CellRef>> {
+        let step = self.history.undone.pop()?;
+        let mut changed = vec![];
+        for command in &step {
+            changed.extend(self.run(command, false));
+        }
+        self.history.done.push(step);
+        Some(changed)
+    }
+
+    // The number of steps which can be undone.
+    pub(super) fn steps(&self) -> usize {
+        self.history.done.len()
+    }
+
+    pub(super) fn record(&mut self, step: Vec<Command>) {
+        if !step.is_empty() {
+            self.history.done.push(step);
+            self.history.undone.clear();
+            if self.history.done.len() > MAX_STEPS {
+                self.history.done.remove(0);
+            }
+        }
+    }
+
+    // Runs a command one way or the other, returning the cells it changed,
+    // which for a structural edit is every cell.
+    fn run(&mut self, command: &Command, undo: bool) -> Vec<CellRef> {
+        match (command, undo) {
+            (Command::Set(r, before, _), true) => {
+                self.put(r.0, r.1, before.clone());
+                return vec![*r];
+            }
+            (Command::Set(r, _, after), false) => {
+                self.put(r.0, r.1, after.clone());
+                return vec![*r];
+            }
+            (Command::Restructure(edit, changed), true) => {
+                self.move_cells(edit.inverse());
+                self.fill(changed.clone());
+            }
+            (Command::Restructure(edit, _), false) => self.move_cells(*edit),
+        }
+        self.cells.keys().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "2".to_string());
+        sheet.set(1, 0, "=A1*10".to_string());
+        sheet.set(0, 0, "3".to_string());
+        assert_eq!(sheet.show_cell(1, 0), "30");
+
+        assert!(sheet.undo());
+        assert_eq!(sheet.raw_cell(0, 0), "2");
+        assert_eq!(sheet.show_cell(1, 0), "20");
+        assert!(sheet.undo());
+        assert!(sheet.undo());
+        assert_eq!(sheet.raw_cell(0, 0), "");
+        assert_eq!(sheet.get_max_dims(), (0, 0));
+        assert!(!sheet.undo());
+
+        assert!(sheet.redo());
+        assert!(sheet.redo());
+        assert_eq!(sheet.show_cell(1, 0), "20");
+
+        // A new edit forgets what could have been redone.
+        sheet.set(2, 0, "x".to_string());
+        assert!(!sheet.redo());
+        assert_eq!(sheet.raw_cell(0, 0), "2");
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut sheet = Spreadsheet::new();
+        for i in 0..MAX_STEPS * 3 {
+            sheet.set(0, 0, i.to_string());
+        }
+        assert_eq!(sheet.steps(), MAX_STEPS);
+        while sheet.undo() {}
+        assert_eq!(sheet.raw_cell(0, 0), (MAX_STEPS * 2 - 1).to_string());
+        assert_eq!(sheet.history.undone.len(), MAX_STEPS);
+        sheet.set(1, 0, "x".to_string());
+        assert!(sheet.history.undone.is_empty());
+    }
+
+    #[test]
+    fn test_clear_range_is_one_step() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "1".to_string());
+        sheet.set(0, 1, "2".to_string());
+        sheet.set(1, 1, "=A1+A2".to_string());
+        sheet.clear_range((0, 0), (1, 1));
+        assert_eq!(sheet.get_max_dims(), (0, 0));
+
+        assert!(sheet.undo());
+        assert_eq!(sheet.show_cell(1, 1), "3");
+        assert_eq!(sheet.get_max_dims(), (1, 1));
+        assert!(sheet.redo());
+        assert_eq!(sheet.raw_cell(1, 1), "");
+    }
+
+    #[test]
+    fn test_undo_structural_edits() {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "1".to_string());
+        sheet.set(0, 1, "2".to_string());
+        sheet.set(0, 2, "=A1 + A2".to_string());
+        sheet.set(1, 2, "=SUM(A1:A2)".to_string());
+
+        sheet.delete_rows(1, 1);
+        assert_eq!(sheet.raw_cell(0, 1), "=A1+#REF!");
+        assert_eq!(sheet.raw_cell(1, 1), "=SUM(A1:A1)");
+        assert!(sheet.undo());
+        assert_eq!(sheet.raw_cell(0, 1), "2");
+        assert_eq!(sheet.raw_cell(0, 2), "=A1 + A2");
+        assert_eq!(sheet.show_cell(1, 2), "3");
+
+        sheet.insert_cols(0, 2);
+        assert_eq!(sheet.raw_cell(3, 2), "=SUM(C1:C2)");
+        assert!(sheet.undo());
+        assert_eq!(sheet.raw_cell(1, 2), "=SUM(A1:A2)");
+        assert_eq!(sheet.get_max_dims(), (1, 2));
+        assert!(sheet.redo());
+        assert_eq!(sheet.show_cell(3, 2), "3");
+    }
+}
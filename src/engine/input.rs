@@ -0,0 +1,188 @@
+// Works out what kind of value a non-formula cell holds from what was typed,
+// so that data cells can take part in arithmetic.
+//
+// Input is tried as a number, a percentage, a boolean and then a date, and
+// anything else is text. Empty input is blank. A leading apostrophe forces
+// text, so that "'123" stays the string "123".
+
+use super::{number_literal, Expr};
+
+pub(super) fn classify(input: &str) -> Expr {
+    if input.is_empty() {
+        return Expr::Blank;
+    }
+    if let Some(text) = input.strip_prefix('\'') {
+        return Expr::String(text.to_string());
+    }
+    let s = input.trim();
+    if let Some(e) = number(s) {
+        return e;
+    }
+    if let Some(x) = s.strip_suffix('%').and_then(|s| number(s.trim_end())) {
+        let x = match x {
+            Expr::Int(x) => x as f64,
+            Expr::Float(x) => x,
+            _ => unreachable!("number only returns numbers"),
+        };
+        return Expr::Float(x / 100.0);
+    }
+    if s.eq_ignore_ascii_case("true") {
+        return Expr::Bool(true);
+    }
+    if s.eq_ignore_ascii_case("false") {
+        return Expr::Bool(false);
+    }
+    if let Some(serial) = date(s) {
+        return Expr::Int(serial);
+    }
+    Expr::String(input.to_string())
+}
+
+// The contents to give a cell so that it holds the text `text`. Text which
+// would otherwise be read as something else, or as a formula, is marked.
+pub(super) fn text_contents(text: &str) -> String {
+    if text.starts_with('=') || classify(text) != Expr::String(text.to_string()) {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Unlike in formulas, a number typed into a cell may have a leading "+".
+fn number(s: &str) -> Option<Expr> {
+    let s = s.strip_prefix('+').unwrap_or(s);
+    // Rust accepts names like "inf" as floats, so insist on a digit.
+    if !s.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.')
+        || !s.contains(|c: char| c.is_ascii_digit())
+    {
+        return None;
+    }
+    number_literal(s)
+}
+
+// Reads an ISO date like "2024-03-15" as a day number. As in other
+// spreadsheets, day 1 is 1900-01-01 (give or take their leap year bug), so
+// the numbers agree with theirs for modern dates.
+pub(super) fn date(s: &str) -> Option<i64> {
+    let parts: Vec<&str> = s.split('-').collect();
+    let (y, m, d) = match parts[..] {
+        [y, m, d] if y.len() == 4 && m.len() == 2 && d.len() == 2 => (y, m, d),
+        _ => return None,
+    };
+    if !s.bytes().all(|c| c.is_ascii_digit() || c == b'-') {
+        return None;
+    }
+    let (y, m, d): (i64, i64, i64) = (y.parse().ok()?, m.parse().ok()?, d.parse().ok()?);
+    if !(1..=12).contains(&m) || d < 1 || d > days_in_month(y, m) {
+        return None;
+    }
+    Some(days_from_civil(y, m, d) - days_from_civil(1899, 12, 30))
+}
+
+// The inverse of `date`: the ISO date for a day number, if it's in a year
+// with four digits.
+pub(super) fn date_text(serial: i64) -> Option<String> {
+    let (y, m, d) = civil_from_days(serial + days_from_civil(1899, 12, 30));
+    match y {
+        1000..=9999 => Some(format!("{:04}-{:02}-{:02}", y, m, d)),
+        _ => None,
+    }
+}
+
+fn days_in_month(y: i64, m: i64) -> i64 {
+    let leap = (y % 4 == 0 && y % 100 != 0) || y % 400 == 0;
+    match m {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// The number of days since 1970-01-01 in the proleptic Gregorian calendar.
+// Years are counted from March so that the leap day falls at the end.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let d = day_of_year - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = year_of_era + era * 400;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(classify("5"), Expr::Int(5));
+        assert_eq!(classify(" -12 "), Expr::Int(-12));
+        assert_eq!(classify("+3"), Expr::Int(3));
+        assert_eq!(classify("1.25"), Expr::Float(1.25));
+        assert_eq!(classify("2e3"), Expr::Float(2000.0));
+        assert_eq!(classify("inf"), Expr::String("inf".to_string()));
+        assert_eq!(classify("1.2.3"), Expr::String("1.2.3".to_string()));
+    }
+
+    #[test]
+    fn test_percentages() {
+        assert_eq!(classify("50%"), Expr::Float(0.5));
+        assert_eq!(classify("-12.5 %"), Expr::Float(-0.125));
+        assert_eq!(classify("%"), Expr::String("%".to_string()));
+    }
+
+    #[test]
+    fn test_booleans() {
+        assert_eq!(classify("TRUE"), Expr::Bool(true));
+        assert_eq!(classify("false"), Expr::Bool(false));
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(classify("1900-03-01"), Expr::Int(61));
+        assert_eq!(classify("2024-03-15"), Expr::Int(45366));
+        assert_eq!(classify("2024-02-29"), Expr::Int(45351));
+        assert_eq!(
+            classify("2023-02-29"),
+            Expr::String("2023-02-29".to_string())
+        );
+        assert_eq!(classify("2024-3-15"), Expr::String("2024-3-15".to_string()));
+        for s in ["1900-03-01", "2000-02-29", "2024-03-15", "9999-12-31"] {
+            assert_eq!(date(s).and_then(date_text).as_deref(), Some(s));
+        }
+    }
+
+    #[test]
+    fn test_forced_text() {
+        assert_eq!(classify("'123"), Expr::String("123".to_string()));
+        assert_eq!(classify("'true"), Expr::String("true".to_string()));
+        assert_eq!(classify("hello"), Expr::String("hello".to_string()));
+        assert_eq!(classify(""), Expr::Blank);
+        assert_eq!(classify("'"), Expr::String("".to_string()));
+    }
+
+    #[test]
+    fn test_text_contents() {
+        assert_eq!(text_contents("hello"), "hello");
+        assert_eq!(text_contents("007"), "'007");
+        assert_eq!(text_contents("=A1"), "'=A1");
+        assert_eq!(text_contents("'quoted"), "''quoted");
+        assert_eq!(text_contents(""), "'");
+    }
+}
@@ -0,0 +1,279 @@
+// Reading and writing sheets as JSON, for passing results on to other
+// programs. Values keep their types: numbers and booleans are written as
+// such, blank cells are null and errors are objects like
+// {"error": "#DIV/0!"}.
+//
+// A sheet is either a list of rows, each a list of values, or a list of
+// records, one for each row after the first, keyed by the text in the first
+// row. Either can be written as a single JSON array, or as newline-delimited
+// JSON with one row or record on each line.
+
+use super::input::text_contents;
+use super::{column_name, CellRef, Error, Spreadsheet, Value};
+use serde_json::{Map, Number, Value as Json};
+
+pub enum JsonShape {
+    Rows,
+    Records,
+}
+
+pub struct JsonOptions {
+    pub shape: JsonShape,
+    // Whether to write each row on a line of its own, without an enclosing
+    // array.
+    pub lines: bool,
+}
+
+impl Default for JsonOptions {
+    fn default() -> JsonOptions {
+        JsonOptions {
+            shape: JsonShape::Rows,
+            lines: false,
+        }
+    }
+}
+
+impl Spreadsheet {
+    // Writes what every cell from A1 to the sheet's extent evaluates to.
+    pub fn to_json(&self, options: &JsonOptions) -> String {
+        let (w, h) = self.get_max_dims();
+        let row = |y| (0..=w).map(move |x| to_json(&self.cell_value(x, y)));
+        let rows: Vec<Json> = match (self.cells.is_empty(), &options.shape) {
+            (true, _) => vec![],
+            (false, JsonShape::Rows) => (0..=h).map(|y| Json::Array(row(y).collect())).collect(),
+            (false, JsonShape::Records) => {
+                let keys = header(row(0).collect());
+                (1..=h)
+                    .map(|y| Json::Object(keys.iter().cloned().zip(row(y)).collect()))
+                    .collect()
+            }
+        };
+
+        let lines: Vec<String> = rows.iter().map(|r| r.to_string()).collect();
+        match (options.lines, lines.is_empty()) {
+            (true, true) => String::new(),
+            (true, false) => lines.join("\n") + "\n",
+            (false, true) => "[]\n".to_string(),
+            (false, false) => format!("[\n  {}\n]\n", lines.join(",\n  ")),
+        }
+    }
+
+    // Reads either shape, whichever the text holds. Records become rows in
+    // the order their keys are first seen, under a header row of the keys.
+    pub fn from_json(text: &str, lines: bool) -> Result<Spreadsheet, Error> {
+        let rows: Vec<Json> = match lines {
+            true => text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| {
+                    serde_json::from_str(line)
+                        .map_err(|e| Error::DescriptiveError(format!("line {}: {}", i + 1, e)))
+                })
+                .collect::<Result<_, _>>()?,
+            false => match serde_json::from_str(text) {
+                Ok(Json::Array(rows)) => rows,
+                Ok(_) => return Err(Error::DescriptiveError("expected an array".to_string())),
+                Err(e) => return Err(Error::DescriptiveError(e.to_string())),
+            },
+        };
+
+        let mut cells = vec![];
+        let mut keys: Vec<String> = vec![];
+        let records = rows.first().is_some_and(Json::is_object);
+        for (i, row) in rows.into_iter().enumerate() {
+            let y = if records { i + 1 } else { i };
+            let error = |msg: &str| Error::DescriptiveError(format!("row {}: {}", i + 1, msg));
+            match (row, records) {
+                (Json::Array(values), false) => {
+                    for (x, v) in values.into_iter().enumerate() {
+                        cells.push((CellRef(x, y), from_json(v).map_err(|e| error(&e))?));
+                    }
+                }
+                (Json::Object(fields), true) => {
+                    for (key, v) in fields {
+                        let x = match keys.iter().position(|k| *k == key) {
+                            Some(x) => x,
+                            None => {
+                                keys.push(key);
+                                keys.len() - 1
+                            }
+                        };
+                        cells.push((CellRef(x, y), from_json(v).map_err(|e| error(&e))?));
+                    }
+                }
+                (_, false) => return Err(error("expected an array of values")),
+                (_, true) => return Err(error("expected an object")),
+            }
+        }
+        for (x, key) in keys.into_iter().enumerate() {
+            cells.push((CellRef(x, 0), text_contents(&key)));
+        }
+
+        let mut sheet = Spreadsheet::new();
+        sheet.fill(cells);
+        Ok(sheet)
+    }
+}
+
+// Keys for records, from the values in the header row. Columns without a
+// header, or with one used already, are keyed by their name instead.
+fn header(values: Vec<Json>) -> Vec<String> {
+    let mut keys: Vec<String> = vec![];
+    for (x, v) in values.into_iter().enumerate() {
+        let key = match v {
+            Json::String(s) => s,
+            Json::Null => String::new(),
+            v => v.to_string(),
+        };
+        if key.is_empty() || keys.contains(&key) {
+            keys.push(column_name(x));
+        } else {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+fn to_json(value: &Value) -> Json {
+    match value {
+        Value::Blank => Json::Null,
+        Value::Int(x) => Json::from(*x),
+        // Cells never hold infinite or NaN floats, so this always succeeds.
+        Value::Float(x) => Number::from_f64(*x).map_or(Json::Null, Json::Number),
+        Value::Bool(b) => Json::Bool(*b),
+        Value::String(s) => Json::String(s.clone()),
+        Value::Array(rows) => rows
+            .iter()
+            .map(|row| Json::Array(row.iter().map(to_json).collect()))
+            .collect(),
+        Value::Error(e) => {
+            let mut error = Map::new();
+            error.insert("error".to_string(), Json::String(e.code().to_string()));
+            Json::Object(error)
+        }
+    }
+}
+
+// The contents to give a cell holding `value`.
+fn from_json(value: Json) -> Result<String, String> {
+    match value {
+        Json::Null => Ok(String::new()),
+        Json::Bool(true) => Ok("TRUE".to_string()),
+        Json::Bool(false) => Ok("FALSE".to_string()),
+        Json::Number(x) => match (x.as_i64(), x.as_f64()) {
+            (Some(x), _) => Ok(x.to_string()),
+            (None, Some(x)) => Ok(format!("{:?}", x)),
+            (None, None) => Err(format!("{} is out of range", x)),
+        },
+        Json::String(s) => Ok(text_contents(&s)),
+        // An error is kept as a formula giving that error.
+        Json::Object(fields) => match fields.get("error") {
+            Some(Json::String(code)) if fields.len() == 1 && Error::from_code(code).is_some() => {
+                Ok(format!("={}", code))
+            }
+            _ => Err(format!("unexpected object {}", Json::Object(fields))),
+        },
+        Json::Array(_) => Err("unexpected array inside a row".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TR = Result<(), Error>;
+
+    fn sheet() -> Spreadsheet {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "name".to_string());
+        sheet.set(1, 0, "score".to_string());
+        sheet.set(0, 1, "Ann".to_string());
+        sheet.set(1, 1, "=3/2".to_string());
+        sheet.set(2, 1, "true".to_string());
+        sheet.set(0, 2, "'12".to_string());
+        sheet.set(1, 2, "=1/0".to_string());
+        sheet
+    }
+
+    #[test]
+    fn test_rows() -> TR {
+        let json = sheet().to_json(&JsonOptions::default());
+        assert_eq!(
+            json,
+            "[\n  [\"name\",\"score\",null],\n  [\"Ann\",1.5,true],\n  [\"12\",{\"error\":\"#DIV/0!\"},null]\n]\n"
+        );
+
+        let loaded = Spreadsheet::from_json(&json, false)?;
+        assert_eq!(loaded.raw_cell(1, 1), "1.5");
+        assert_eq!(loaded.raw_cell(2, 1), "TRUE");
+        assert_eq!(loaded.raw_cell(0, 2), "'12");
+        assert_eq!(loaded.raw_cell(1, 2), "=#DIV/0!");
+        assert_eq!(loaded.get_max_dims(), (2, 2));
+        assert_eq!(loaded.to_json(&JsonOptions::default()), json);
+        Ok(())
+    }
+
+    #[test]
+    fn test_records() -> TR {
+        let options = JsonOptions {
+            shape: JsonShape::Records,
+            lines: true,
+        };
+        let json = sheet().to_json(&options);
+        assert_eq!(
+            json,
+            "{\"name\":\"Ann\",\"score\":1.5,\"C\":true}\n{\"name\":\"12\",\"score\":{\"error\":\"#DIV/0!\"},\"C\":null}\n"
+        );
+
+        let loaded = Spreadsheet::from_json(&json, true)?;
+        assert_eq!(loaded.raw_cell(0, 0), "name");
+        assert_eq!(loaded.raw_cell(2, 0), "C");
+        assert_eq!(loaded.raw_cell(1, 1), "1.5");
+        assert_eq!(loaded.to_json(&options), json);
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_with_different_keys() -> TR {
+        let json = "[{\"a\": 1, \"b\": 2}, {\"c\": \"x\", \"a\": 3}]";
+        let loaded = Spreadsheet::from_json(json, false)?;
+        let row = |y| (0..3).map(|x| loaded.raw_cell(x, y)).collect::<Vec<_>>();
+        assert_eq!(row(0), ["a", "b", "c"]);
+        assert_eq!(row(1), ["1", "2", ""]);
+        assert_eq!(row(2), ["3", "", "x"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() {
+        let message = |text: &str, lines| match Spreadsheet::from_json(text, lines) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        };
+        assert_eq!(message("{}", false), "expected an array");
+        assert_eq!(
+            message("[[1], 2]", false),
+            "row 2: expected an array of values"
+        );
+        assert_eq!(
+            message("[[[1]]]", false),
+            "row 1: unexpected array inside a row"
+        );
+        assert_eq!(
+            message("[1]\n\n[2", true),
+            "line 3: EOF while parsing a list at line 1 column 2"
+        );
+    }
+
+    #[test]
+    fn test_empty_sheet() {
+        let sheet = Spreadsheet::new();
+        assert_eq!(sheet.to_json(&JsonOptions::default()), "[]\n");
+        let options = JsonOptions {
+            shape: JsonShape::Records,
+            lines: true,
+        };
+        assert_eq!(sheet.to_json(&options), "");
+    }
+}
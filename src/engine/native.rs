@@ -0,0 +1,360 @@
+// The native file format, which keeps everything about a sheet and is meant
+// to diff well under version control. For example:
+//
+// ```
+// spreadsheet 1
+// meta generator	spreadsheet 0.1.0
+// # Comments and blank lines are ignored.
+// A1	Price
+// B1	2.50
+// B2	=B1*2	future-field=something
+// ```
+//
+// The first line names the format and its version. Lines after it starting
+// with "meta" hold facts about the file, each a key, a tab and a value, like
+// the program which wrote it. Each cell is on a line
+// of its own, in row order: its address, a tab, and its raw contents, with
+// backslash escapes for tabs, line breaks and backslashes. Empty cells are
+// left out.
+//
+// A workbook's sheets each start with a line naming the sheet, like
+// "sheet Q1 Sales", followed by the sheet's cells. Files with sheet lines
+// are version 2; a single sheet is still written as version 1. The
+// workbook's names come before its sheets, each on a line like
+// "name TaxRate\t0.2": the name, a tab, and what it stands for.
+//
+// So that files written by later versions can still be read, any further
+// tab-separated fields on a cell's line are ignored, as are lines starting
+// with a word this version doesn't know, like "name" lines were by version
+// 1. The version number only goes up for changes which an older version
+// would misread by ignoring them, so that it rejects the file instead. Sheet
+// lines are one: the cells after each belong to a different sheet, which a
+// version 1 reader would mix together.
+
+use super::{a1_name, parse_a1, CellRef, Error, Spreadsheet, Workbook};
+
+const FORMAT: &str = "spreadsheet";
+const VERSION: u32 = 2;
+
+// The metadata written after the header line.
+fn meta_lines() -> String {
+    format!(
+        "meta generator\t{} {}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+impl Spreadsheet {
+    pub fn to_native(&self) -> String {
+        format!("{} 1\n{}{}", FORMAT, meta_lines(), cell_lines(self))
+    }
+
+    // Errors give the line number they were found on. Only the first sheet
+    // of a workbook is read.
+    pub fn from_native(text: &str) -> Result<Spreadsheet, Error> {
+        let (_, cells) = read(text)?.0.remove(0);
+        let mut sheet = Spreadsheet::new();
+        sheet.fill(cells);
+        Ok(sheet)
+    }
+}
+
+impl Workbook {
+    pub fn to_native(&self) -> String {
+        let mut out = format!("{} {}\n{}", FORMAT, VERSION, meta_lines());
+        for (name, definition) in self.names() {
+            out.push_str(&format!("name {}\t{}\n", escape(name), escape(&definition)));
+        }
+        for (name, sheet) in self.sheets() {
+            out.push_str(&format!("sheet {}\n{}", escape(name), cell_lines(sheet)));
+        }
+        out
+    }
+
+    // A file without sheet lines is read as a workbook of one sheet.
+    pub fn from_native(text: &str) -> Result<Workbook, Error> {
+        let (sections, names) = read(text)?;
+        let sheets = sections
+            .into_iter()
+            .map(|(name, cells)| {
+                let mut sheet = Spreadsheet::new();
+                sheet.fill(cells);
+                (name.unwrap_or_else(|| "Sheet1".to_string()), sheet)
+            })
+            .collect();
+        let mut book = Workbook::from_sheets(sheets)?;
+        for (n, name, definition) in names {
+            book.define_name(&name, &definition)
+                .map_err(|e| Error::DescriptiveError(format!("line {}: {}", n, e)))?;
+        }
+        Ok(book)
+    }
+}
+
+fn cell_lines(sheet: &Spreadsheet) -> String {
+    let mut cells: Vec<(&CellRef, &String)> = sheet
+        .cells
+        .iter()
+        .filter(|(_, c)| !c.contents.is_empty())
+        .map(|(r, c)| (r, &c.contents))
+        .collect();
+    cells.sort_by_key(|(r, _)| (r.1, r.0));
+    cells
+        .into_iter()
+        .map(|(r, contents)| format!("{}\t{}\n", a1_name(r.0, r.1), escape(contents)))
+        .collect()
+}
+
+type Section = (Option<String>, Vec<(CellRef, String)>);
+// A name and its definition, with the line it was on.
+type Definition = (usize, String, String);
+
+// Reads the cells of each sheet in the file, with the sheet's name, and the
+// names defined. There is always at least one sheet, which has no name if the
+// file has no sheet lines.
+fn read(text: &str) -> Result<(Vec<Section>, Vec<Definition>), Error> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+    let error = |n: usize, msg: String| Error::DescriptiveError(format!("line {}: {}", n, msg));
+
+    let (n, header) = lines
+        .next()
+        .ok_or_else(|| error(1, "the file is empty".to_string()))?;
+    if let Err(msg) = check_header(header, VERSION) {
+        // Saying what wrote a newer file helps to find something to read it.
+        let generator = lines
+            .clone()
+            .find_map(|(_, line)| line.strip_prefix("meta generator\t"));
+        return Err(error(
+            n,
+            match generator {
+                Some(generator) => format!("{}, written by {}", msg, generator),
+                None => msg,
+            },
+        ));
+    }
+
+    let mut sections: Vec<Section> = vec![(None, vec![])];
+    let mut names = vec![];
+    let mut seen = std::collections::HashSet::new();
+    for (n, line) in lines {
+        if let Some(name) = line.strip_prefix("sheet ") {
+            let name = unescape(name).map_err(|msg| error(n, msg))?;
+            match sections.last_mut() {
+                Some((None, cells)) if cells.is_empty() => sections[0].0 = Some(name),
+                _ => sections.push((Some(name), vec![])),
+            }
+            seen.clear();
+            continue;
+        }
+        if line.starts_with("meta ") {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("name ") {
+            let (name, definition) = rest.split_once('\t').unwrap_or((rest, ""));
+            let name = unescape(name).map_err(|msg| error(n, msg))?;
+            let definition = unescape(definition).map_err(|msg| error(n, msg))?;
+            names.push((n, name, definition));
+            continue;
+        }
+        let (address, rest) = line.split_once('\t').unwrap_or((line, ""));
+        let (x, y) = match parse_a1(address) {
+            Some(xy) => xy,
+            // A line for something a later version added.
+            None if address.starts_with(|c: char| c.is_ascii_lowercase()) => continue,
+            None => return Err(error(n, format!("\"{}\" is not a cell address", address))),
+        };
+        if !seen.insert((x, y)) {
+            return Err(error(n, format!("{} appears more than once", address)));
+        }
+        let contents = rest.split('\t').next().unwrap_or("");
+        let contents = unescape(contents).map_err(|msg| error(n, msg))?;
+        if let Some((_, cells)) = sections.last_mut() {
+            cells.push((CellRef(x, y), contents));
+        }
+    }
+    Ok((sections, names))
+}
+
+// Checks the header line names the format, with a version no newer than
+// `max_version`.
+fn check_header(header: &str, max_version: u32) -> Result<(), String> {
+    let version = match header.split_once(' ') {
+        Some((FORMAT, version)) => version,
+        _ => return Err(format!("expected \"{} {}\"", FORMAT, VERSION)),
+    };
+    match version.trim().parse::<u32>() {
+        Ok(v) if v <= max_version => Ok(()),
+        Ok(v) => Err(format!(
+            "version {} is newer than this program can read (up to {})",
+            v, max_version
+        )),
+        Err(_) => Err(format!("\"{}\" is not a version number", version)),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => return Err(format!("unknown escape \"\\{}\"", c)),
+            None => return Err("a backslash at the end of the line".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TR = Result<(), Error>;
+
+    #[test]
+    fn test_round_trip() -> TR {
+        let mut sheet = Spreadsheet::new();
+        sheet.set(1, 1, "=A1*2".to_string());
+        sheet.set(0, 0, "2.50".to_string());
+        sheet.set(2, 0, "tab\there\\ and\nnewline".to_string());
+        sheet.set(0, 3, "'007".to_string());
+        let text = sheet.to_native();
+        assert_eq!(
+            text,
+            "spreadsheet 1\nmeta generator\tspreadsheet 0.1.0\nA1\t2.50\nC1\ttab\\there\\\\ and\\nnewline\nB2\t=A1*2\nA4\t'007\n"
+                .replace("0.1.0", env!("CARGO_PKG_VERSION"))
+        );
+
+        let loaded = Spreadsheet::from_native(&text)?;
+        assert_eq!(loaded.raw_cell(2, 0), "tab\there\\ and\nnewline");
+        assert_eq!(loaded.show_cell(1, 1), "5");
+        assert_eq!(loaded.get_max_dims(), (2, 3));
+        assert_eq!(loaded.to_native(), text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignores_unknown_fields() -> TR {
+        let text = "# made by hand\nspreadsheet 1\n\nA1\t3\tformat=bold\ntheme\tdark\nB1\t=A1+1\n";
+        let sheet = Spreadsheet::from_native(text)?;
+        assert_eq!(sheet.raw_cell(0, 0), "3");
+        assert_eq!(sheet.show_cell(1, 0), "4");
+        Ok(())
+    }
+
+    #[test]
+    fn test_workbook() -> TR {
+        let mut book = Workbook::new();
+        book.set(0, 0, "5".to_string());
+        book.add_sheet("Q1\tSales")?;
+        book.switch_sheet("Q1\tSales")?;
+        book.set(1, 0, "=Sheet1!A1*2".to_string());
+        let text = book.to_native();
+        assert_eq!(
+            text,
+            "spreadsheet 2\nmeta generator\tspreadsheet 0.1.0\nsheet Sheet1\nA1\t5\nsheet Q1\\tSales\nB1\t=Sheet1!A1*2\n"
+                .replace("0.1.0", env!("CARGO_PKG_VERSION"))
+        );
+
+        let loaded = Workbook::from_native(&text)?;
+        let (name, sheet) = loaded.sheets()[1];
+        assert_eq!(name, "Q1\tSales");
+        assert_eq!(sheet.show_cell(1, 0), "10");
+        assert_eq!(loaded.to_native(), text);
+        assert_eq!(Spreadsheet::from_native(&text)?.raw_cell(0, 0), "5");
+
+        let single = Workbook::from_native("spreadsheet 1\nA1\t1\n")?;
+        assert_eq!(single.sheet_name(), "Sheet1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_names() -> TR {
+        let mut book = Workbook::new();
+        book.set(0, 0, "8".to_string());
+        book.define_name("Rate", "0.5")?;
+        book.define_name("Price", "A1")?;
+        book.set(1, 0, "=Rate*Price".to_string());
+        let text = book.to_native();
+        assert_eq!(
+            text,
+            "spreadsheet 2\nmeta generator\tspreadsheet 0.1.0\nname Rate\t0.5\nname Price\tSheet1!A1\nsheet Sheet1\nA1\t8\nB1\t=Rate*Price\n"
+                .replace("0.1.0", env!("CARGO_PKG_VERSION"))
+        );
+
+        let loaded = Workbook::from_native(&text)?;
+        assert_eq!(loaded.sheet().show_cell(1, 0), "4");
+        assert_eq!(loaded.to_native(), text);
+
+        match Workbook::from_native("spreadsheet 2\nname A1\t1\n") {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => assert_eq!(err.to_string(), "line 2: \"A1\" can't be used as a name"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_old_reader_rejects_workbooks() -> TR {
+        let mut book = Workbook::new();
+        book.add_sheet("Other")?;
+        let text = book.to_native();
+        let header = text.lines().next().unwrap_or("");
+        assert_eq!(
+            check_header(header, 1),
+            Err("version 2 is newer than this program can read (up to 1)".to_string())
+        );
+        // A single sheet is still readable by version 1.
+        let text = Spreadsheet::new().to_native();
+        assert_eq!(check_header(text.lines().next().unwrap_or(""), 1), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() {
+        let message = |text: &str| match Spreadsheet::from_native(text) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        };
+        assert_eq!(message(""), "line 1: the file is empty");
+        assert_eq!(message("a,b\n"), "line 1: expected \"spreadsheet 2\"");
+        assert_eq!(
+            message("spreadsheet 3\n"),
+            "line 1: version 3 is newer than this program can read (up to 2)"
+        );
+        assert_eq!(
+            message("spreadsheet 3\nmeta generator\tspreadsheet 9.0\n"),
+            "line 1: version 3 is newer than this program can read (up to 2), written by spreadsheet 9.0"
+        );
+        assert_eq!(
+            message("spreadsheet 1\nA1\t1\n\nA0\t2\n"),
+            "line 4: \"A0\" is not a cell address"
+        );
+        assert_eq!(
+            message("spreadsheet 1\nA1\t1\na1\t2\n"),
+            "line 3: a1 appears more than once"
+        );
+        assert_eq!(
+            message("spreadsheet 1\nA1\tbad \\q\n"),
+            "line 2: unknown escape \"\\q\""
+        );
+    }
+}
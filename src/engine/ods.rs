@@ -0,0 +1,830 @@
+// Reading and writing OpenDocument spreadsheets (.ods), as used by
+// LibreOffice. Like .xlsx files these are zip archives of XML, and only cell
+// contents are kept. Percentages and dates have value types of their own,
+// and a number typed with extra zeros, like "2.50", gets a number style
+// showing them, so that it reads back the way it was typed.
+//
+// Formulas are written in OpenFormula, which is Excel's syntax with the
+// references in brackets, like "[.A1:.B2]", and arguments separated by ";".
+// TRUE and FALSE are functions rather than values. We translate to and from
+// Excel's syntax and then to our own. Anything we can't translate, such as a
+// range spanning several sheets or a function from an extension, is kept as
+// it was written, so that saving the file again gives the same formula back.
+
+use super::input::{classify, date, text_contents};
+use super::parsing::{tokenize, TokenKind};
+use super::xlsx::{number_format, parse_excel, read_part, to_excel};
+use super::{parse_reference, Cell, CellRef, Error, Expr, Spreadsheet, Value};
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+const NAMESPACES: &str = "xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" \
+     xmlns:table=\"urn:oasis:names:tc:opendocument:xmlns:table:1.0\" \
+     xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\" \
+     xmlns:style=\"urn:oasis:names:tc:opendocument:xmlns:style:1.0\" \
+     xmlns:number=\"urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0\" \
+     xmlns:of=\"urn:oasis:names:tc:opendocument:xmlns:of:1.2\"";
+
+// Returns every sheet in the document with its name, in order.
+pub fn read_ods(bytes: &[u8]) -> Result<Vec<(String, Spreadsheet)>, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    content(&read_part(&mut archive, "content.xml")?)
+}
+
+pub fn write_ods(sheets: &[(&str, &Spreadsheet)]) -> Result<Vec<u8>, Error> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The media type comes first and uncompressed, so that it can be found
+    // without unzipping anything.
+    zip.start_file(
+        "mimetype",
+        FileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(MIMETYPE.as_bytes())?;
+
+    zip.start_file("META-INF/manifest.xml", FileOptions::default())?;
+    zip.write_all(
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <manifest:manifest xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\" \
+             manifest:version=\"1.2\">\
+             <manifest:file-entry manifest:full-path=\"/\" manifest:version=\"1.2\" manifest:media-type=\"{}\"/>\
+             <manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>\
+             </manifest:manifest>",
+            MIMETYPE
+        )
+        .as_bytes(),
+    )?;
+
+    let mut formats = vec![];
+    let tables: String = sheets
+        .iter()
+        .map(|(name, sheet)| table_xml(name, sheet, &mut formats))
+        .collect();
+    zip.start_file("content.xml", FileOptions::default())?;
+    zip.write_all(
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <office:document-content {} office:version=\"1.2\">\
+             <office:automatic-styles>{}</office:automatic-styles>\
+             <office:body><office:spreadsheet>{}</office:spreadsheet></office:body>\
+             </office:document-content>",
+            NAMESPACES,
+            styles_xml(&formats),
+            tables
+        )
+        .as_bytes(),
+    )?;
+    Ok(zip.finish()?.into_inner())
+}
+
+// Runs of empty rows and cells are written as one repeated element. Cells
+// with a number format use the style at its position in `formats`, adding
+// it if it's new.
+fn table_xml(name: &str, sheet: &Spreadsheet, formats: &mut Vec<String>) -> String {
+    let mut rows: BTreeMap<usize, Vec<(usize, &Cell)>> = BTreeMap::new();
+    for (r, cell) in &sheet.cells {
+        if !cell.contents.is_empty() {
+            rows.entry(r.1).or_default().push((r.0, cell));
+        }
+    }
+
+    let repeated = |what: &str, n: usize| match n {
+        1 => String::new(),
+        n => format!(" table:number-{}-repeated=\"{}\"", what, n),
+    };
+    let mut xml = format!(
+        "<table:table table:name=\"{}\"><table:table-column{}/>",
+        escape(name),
+        repeated("columns", sheet.get_max_dims().0 + 1)
+    );
+    let mut next_y = 0;
+    for (y, mut cells) in rows {
+        if y > next_y {
+            xml.push_str(&format!(
+                "<table:table-row{}><table:table-cell/></table:table-row>",
+                repeated("rows", y - next_y)
+            ));
+        }
+        cells.sort_by_key(|(x, _)| *x);
+        xml.push_str("<table:table-row>");
+        let mut next_x = 0;
+        for (x, cell) in cells {
+            if x > next_x {
+                xml.push_str(&format!(
+                    "<table:table-cell{}/>",
+                    repeated("columns", x - next_x)
+                ));
+            }
+            let style = number_format(&cell.contents).map(|f| {
+                formats.iter().position(|g| *g == f).unwrap_or_else(|| {
+                    formats.push(f);
+                    formats.len() - 1
+                })
+            });
+            xml.push_str(&cell_xml(cell, style));
+            next_x = x + 1;
+        }
+        xml.push_str("</table:table-row>");
+        next_y = y + 1;
+    }
+    if next_y == 0 {
+        xml.push_str("<table:table-row><table:table-cell/></table:table-row>");
+    }
+    xml.push_str("</table:table>");
+    xml
+}
+
+// Cells hold their value as an attribute, and the text shown for it in
+// paragraphs. A cell with a number format shows its contents as typed.
+fn cell_xml(cell: &Cell, style: Option<usize>) -> String {
+    let mut attrs = String::new();
+    if let Some(i) = style {
+        attrs.push_str(&format!(" table:style-name=\"ce{}\"", i + 1));
+    }
+    if let Some(text) = cell.contents.strip_prefix('=') {
+        let formula = match &cell.expr {
+            Ok(e) => to_openformula(e),
+            Err(_) => text.to_string(),
+        };
+        attrs.push_str(&format!(
+            " table:formula=\"of:={}\"",
+            escape(formula.as_str())
+        ));
+    }
+    let typed = cell.contents.trim();
+    let shown = match &cell.value {
+        Value::Int(_) if style.is_some() && date(typed).is_some() => {
+            attrs.push_str(&format!(
+                " office:value-type=\"date\" office:date-value=\"{}\"",
+                typed
+            ));
+            typed.to_string()
+        }
+        Value::Float(x) if style.is_some() && typed.ends_with('%') => {
+            attrs.push_str(&format!(
+                " office:value-type=\"percentage\" office:value=\"{:?}\"",
+                x
+            ));
+            typed.to_string()
+        }
+        Value::Int(_) | Value::Float(_) => {
+            let x = match &cell.value {
+                Value::Float(x) => format!("{:?}", x),
+                v => v.to_string(),
+            };
+            attrs.push_str(&format!(
+                " office:value-type=\"float\" office:value=\"{}\"",
+                x
+            ));
+            match style {
+                Some(_) => typed.to_string(),
+                None => cell.value.to_string(),
+            }
+        }
+        Value::Bool(b) => {
+            attrs.push_str(&format!(
+                " office:value-type=\"boolean\" office:boolean-value=\"{}\"",
+                b
+            ));
+            b.to_string().to_uppercase()
+        }
+        Value::String(s) => {
+            attrs.push_str(" office:value-type=\"string\"");
+            s.clone()
+        }
+        Value::Error(e) => {
+            attrs.push_str(" office:value-type=\"string\"");
+            e.code().to_string()
+        }
+        Value::Blank | Value::Array(_) => String::new(),
+    };
+    let paragraphs: String = match shown.is_empty() {
+        true => String::new(),
+        false => shown.split('\n').map(paragraph).collect(),
+    };
+    format!(
+        "<table:table-cell{}>{}</table:table-cell>",
+        attrs, paragraphs
+    )
+}
+
+// A cell style for each number format, named "ce1" and so on, with the data
+// style it uses. Formats are ones made by `number_format`: a date, a
+// percentage, or a number of digits either side of the point.
+fn styles_xml(formats: &[String]) -> String {
+    let mut xml = String::new();
+    for (i, f) in formats.iter().enumerate() {
+        let n = i + 1;
+        let digits = |f: &str| {
+            let (int, frac) = f.split_once('.').unwrap_or((f, ""));
+            format!(
+                "<number:number number:decimal-places=\"{}\" \
+                 number:min-decimal-places=\"{}\" number:min-integer-digits=\"{}\"/>",
+                frac.len(),
+                frac.len(),
+                int.len()
+            )
+        };
+        xml.push_str(&match f.strip_suffix('%') {
+            _ if f == "yyyy-mm-dd" => format!(
+                "<number:date-style style:name=\"N{}\"><number:year number:style=\"long\"/>\
+                 <number:text>-</number:text><number:month number:style=\"long\"/>\
+                 <number:text>-</number:text><number:day number:style=\"long\"/>\
+                 </number:date-style>",
+                n
+            ),
+            Some(f) => format!(
+                "<number:percentage-style style:name=\"N{}\">{}\
+                 <number:text>%</number:text></number:percentage-style>",
+                n,
+                digits(f)
+            ),
+            None => format!(
+                "<number:number-style style:name=\"N{}\">{}</number:number-style>",
+                n,
+                digits(f)
+            ),
+        });
+        xml.push_str(&format!(
+            "<style:style style:name=\"ce{}\" style:family=\"table-cell\" \
+             style:data-style-name=\"N{}\"/>",
+            n, n
+        ));
+    }
+    xml
+}
+
+// Readers collapse runs of spaces in paragraphs, so all but the first space
+// of a run are written as a count. Spaces at the start are all counted.
+fn paragraph(line: &str) -> String {
+    let mut xml = String::from("<text:p>");
+    let mut spaces = 0;
+    let flush = |xml: &mut String, spaces: &mut usize, at_start: bool| {
+        let counted = match at_start {
+            true => *spaces,
+            false => *spaces - 1,
+        };
+        if !at_start && *spaces > 0 {
+            xml.push(' ');
+        }
+        match counted {
+            0 => (),
+            1 => xml.push_str("<text:s/>"),
+            n => xml.push_str(&format!("<text:s text:c=\"{}\"/>", n)),
+        }
+        *spaces = 0;
+    };
+    let mut at_start = true;
+    for c in line.chars() {
+        if c == ' ' {
+            spaces += 1;
+            continue;
+        }
+        if spaces > 0 {
+            flush(&mut xml, &mut spaces, at_start);
+        }
+        at_start = false;
+        match c {
+            '\t' => xml.push_str("<text:tab/>"),
+            c => xml.push_str(&escape(&*c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    if spaces > 0 {
+        flush(&mut xml, &mut spaces, at_start);
+    }
+    xml.push_str("</text:p>");
+    xml
+}
+
+// An element's attributes, keyed by their full names. Namespaces matter
+// here, since there are both "office:value-type" and "calcext:value-type".
+fn attributes(e: &BytesStart) -> Result<HashMap<String, String>, Error> {
+    let mut attrs = HashMap::new();
+    for a in e.attributes() {
+        let a = a.map_err(quick_xml::Error::from)?;
+        let key = String::from_utf8_lossy(a.key.as_ref()).into_owned();
+        attrs.insert(key, a.unescape_value()?.into_owned());
+    }
+    Ok(attrs)
+}
+
+fn repeats(attrs: &HashMap<String, String>, what: &str) -> Result<usize, Error> {
+    match attrs.get(&format!("table:number-{}-repeated", what)) {
+        Some(n) => Ok(n.parse::<usize>()?.max(1)),
+        None => Ok(1),
+    }
+}
+
+// What we've read of a cell so far.
+struct CellXml {
+    attrs: HashMap<String, String>,
+    repeat: usize,
+    paragraphs: Vec<String>,
+}
+
+fn content(xml: &str) -> Result<Vec<(String, Spreadsheet)>, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut sheets = vec![];
+    let mut name = String::new();
+    let mut cells: Vec<(CellRef, String)> = vec![];
+    let (mut x, mut y) = (0, 0);
+    // How many times the current row repeats, and where its cells start.
+    let mut row_repeat = 1;
+    let mut row_start = 0;
+    let mut cell: Option<CellXml> = None;
+    // Comments attached to cells have paragraphs too, which we skip.
+    let mut in_annotation = 0;
+    // Text between elements, like the indentation of pretty-printed files,
+    // is only part of the cell inside a paragraph.
+    let mut in_paragraph = false;
+
+    loop {
+        let (e, empty) = match reader.read_event()? {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"table:table" => {
+                        let mut sheet = Spreadsheet::new();
+                        sheet.fill(std::mem::take(&mut cells));
+                        sheets.push((std::mem::take(&mut name), sheet));
+                    }
+                    b"table:table-row" => {
+                        end_row(&mut cells, row_start, row_repeat, y);
+                        y += row_repeat;
+                    }
+                    b"table:table-cell" | b"table:covered-table-cell" => {
+                        if let Some(c) = cell.take() {
+                            end_cell(&mut cells, c, &mut x, y);
+                        }
+                    }
+                    b"office:annotation" => in_annotation -= 1,
+                    b"text:p" => in_paragraph = false,
+                    _ => (),
+                }
+                continue;
+            }
+            Event::Text(t) => {
+                if let Some(p) = cell.as_mut().and_then(|c| c.paragraphs.last_mut()) {
+                    if in_annotation == 0 && in_paragraph {
+                        p.push_str(&t.unescape()?);
+                    }
+                }
+                continue;
+            }
+            Event::Eof => return Ok(sheets),
+            _ => continue,
+        };
+
+        match e.name().as_ref() {
+            b"table:table" => {
+                name = attributes(&e)?.remove("table:name").unwrap_or_default();
+                y = 0;
+                // A table without rows has no end to push it at.
+                if empty {
+                    sheets.push((std::mem::take(&mut name), Spreadsheet::new()));
+                }
+            }
+            b"table:table-row" => {
+                let attrs = attributes(&e)?;
+                row_repeat = repeats(&attrs, "rows")?;
+                row_start = cells.len();
+                x = 0;
+                if empty {
+                    y += row_repeat;
+                }
+            }
+            b"table:table-cell" | b"table:covered-table-cell" => {
+                let attrs = attributes(&e)?;
+                let c = CellXml {
+                    repeat: repeats(&attrs, "columns")?,
+                    attrs,
+                    paragraphs: vec![],
+                };
+                match empty {
+                    true => end_cell(&mut cells, c, &mut x, y),
+                    false => cell = Some(c),
+                }
+            }
+            b"office:annotation" if !empty => in_annotation += 1,
+            _ if in_annotation > 0 => (),
+            b"text:p" => {
+                if let Some(c) = cell.as_mut() {
+                    c.paragraphs.push(String::new());
+                }
+                in_paragraph = !empty;
+            }
+            b"text:s" | b"text:tab" | b"text:line-break" if in_paragraph => {
+                let text = match e.name().as_ref() {
+                    b"text:s" => " ".repeat(match attributes(&e)?.get("text:c") {
+                        Some(n) => n.parse::<usize>()?,
+                        None => 1,
+                    }),
+                    b"text:tab" => "\t".to_string(),
+                    _ => "\n".to_string(),
+                };
+                if let Some(p) = cell.as_mut().and_then(|c| c.paragraphs.last_mut()) {
+                    p.push_str(&text);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// A cell repeated across several columns has the same contents in each.
+fn end_cell(cells: &mut Vec<(CellRef, String)>, c: CellXml, x: &mut usize, y: usize) {
+    if let Some(contents) = cell_contents(&c) {
+        for i in 0..c.repeat {
+            cells.push((CellRef(*x + i, y), contents.clone()));
+        }
+    }
+    *x += c.repeat;
+}
+
+// Files often end with a row repeated a million times, but it's empty, so
+// there's nothing to copy.
+fn end_row(cells: &mut Vec<(CellRef, String)>, start: usize, repeat: usize, y: usize) {
+    let row: Vec<(CellRef, String)> = cells[start..].to_vec();
+    for i in 1..repeat {
+        cells.extend(row.iter().map(|(r, s)| (CellRef(r.0, y + i), s.clone())));
+    }
+}
+
+fn cell_contents(c: &CellXml) -> Option<String> {
+    if let Some(formula) = c.attrs.get("table:formula") {
+        return Some(from_openformula(formula));
+    }
+    let get = |key: &str| c.attrs.get(key).cloned();
+    let shown = c.paragraphs.join("\n");
+    let contents = match c.attrs.get("office:value-type").map(String::as_str) {
+        // The text shown is kept when it's the same number, so that one
+        // shown with extra zeros keeps them.
+        Some("float") => {
+            let value = get("office:value")?;
+            match (value.parse::<f64>(), classify(&shown)) {
+                (Ok(x), Expr::Int(y)) if x == y as f64 => shown,
+                (Ok(x), Expr::Float(y)) if x == y => shown,
+                _ => value,
+            }
+        }
+        Some("percentage") => percent_text(&get("office:value")?)?,
+        Some("currency") => get("office:value")?,
+        Some("boolean") => match get("office:boolean-value")?.as_str() {
+            "true" => "TRUE".to_string(),
+            _ => "FALSE".to_string(),
+        },
+        Some("date") => date_value(&get("office:date-value")?)?,
+        Some("string") => text_contents(&get("office:string-value").unwrap_or(shown)),
+        // Times and anything newer are kept as they're shown.
+        Some(_) => shown,
+        None => return None,
+    };
+    Some(contents).filter(|s| !s.is_empty())
+}
+
+// A percentage's value as it would be typed, like "12.5%" for 0.125. The
+// value is rounded a little, since multiplying by 100 isn't exact.
+fn percent_text(value: &str) -> Option<String> {
+    let x = value.parse::<f64>().ok()? * 100.0;
+    Some(format!("{}%", (x * 1e9).round() / 1e9))
+}
+
+// A date value like "2024-03-15", or "2024-03-15T10:30:00" with a time of
+// day. A date alone is typed as it is, but we can't type a time, so a date
+// with one becomes its day number with the time as a fraction.
+fn date_value(value: &str) -> Option<String> {
+    let (day, time) = match value.split_once('T') {
+        Some((day, time)) => (day, time),
+        None => return Some(value.to_string()),
+    };
+    let serial = date(day)?;
+    let mut seconds = 0.0;
+    for (part, scale) in time.split(':').zip([3600.0, 60.0, 1.0]) {
+        seconds += part.parse::<f64>().ok()? * scale;
+    }
+    Some(match seconds {
+        0.0 => day.to_string(),
+        _ => format!("{:?}", serial as f64 + seconds / 86400.0),
+    })
+}
+
+// Turns a formula as stored in a file, like "of:=SUM([.A1:.A3])", into cell
+// contents, with the "=".
+pub(super) fn from_openformula(formula: &str) -> String {
+    // The namespace in front says which syntax the formula is in. Files from
+    // older programs may use something other than OpenFormula, but it's
+    // close enough to try.
+    let body = match formula.split_once('=') {
+        Some((ns, body)) if ns.is_empty() || ns.ends_with(':') => body,
+        _ => formula,
+    };
+    match excel_syntax(body).and_then(|f| parse_excel(&f)) {
+        Some(e) => format!("={}", e),
+        None => format!("={}", body),
+    }
+}
+
+// Writes a formula in OpenFormula, with its namespace.
+pub(super) fn to_openformula(expr: &Expr) -> String {
+    let excel = to_excel(expr);
+    let tokens = match tokenize(&excel) {
+        Ok(tokens) => tokens,
+        Err(_) => return excel,
+    };
+    // A name before a bracket is a function even if it looks like a cell.
+    let is_ref = |i: usize| {
+        tokens.get(i).is_some_and(|t| {
+            t.kind == TokenKind::Ident
+                && parse_reference(&t.text).is_some()
+                && tokens.get(i + 1).is_none_or(|t| t.text != "(")
+        })
+    };
+    let mut out = String::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let t = &tokens[i];
+        // A reference to another sheet starts with the sheet's name.
+        let (sheet, at) = match tokens.get(i + 1) {
+            Some(next) if next.text == "!" && next.kind == TokenKind::Punct => {
+                (format!("${}", t.text), i + 2)
+            }
+            _ => (String::new(), i),
+        };
+        if is_ref(at) && tokens.get(at + 1).is_some_and(|t| t.text == ":") && is_ref(at + 2) {
+            out.push_str(&format!(
+                "[{}.{}:.{}]",
+                sheet,
+                tokens[at].text,
+                tokens[at + 2].text
+            ));
+            i = at + 3;
+            continue;
+        }
+        if is_ref(at) {
+            out.push_str(&format!("[{}.{}]", sheet, tokens[at].text));
+            i = at + 1;
+            continue;
+        }
+        match (t.kind, t.text.as_str()) {
+            (TokenKind::Punct, ",") => out.push(';'),
+            (TokenKind::Ident, "TRUE" | "FALSE") => out.push_str(&format!("{}()", t.text)),
+            _ => out.push_str(&t.text),
+        }
+        i += 1;
+    }
+    out
+}
+
+// Rewrites OpenFormula in Excel's syntax, or gives None for ranges spanning
+// several sheets.
+fn excel_syntax(formula: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = formula;
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '"' => 1 + rest[1..].find('"')? + 1,
+            '[' => {
+                let end = rest.find(']')?;
+                // Each end of a reference starts with the sheet's name and a
+                // dot. The name is left out for the sheet the formula is on,
+                // and for the second end of a range on the same sheet.
+                let mut sheet = None;
+                let mut cells = vec![];
+                for (i, part) in rest[1..end].split(':').enumerate() {
+                    let (name, cell) = part.rsplit_once('.')?;
+                    match (i, name.strip_prefix('$').unwrap_or(name)) {
+                        (_, "") => (),
+                        (0, name) => sheet = Some(name),
+                        (_, name) if Some(name) == sheet => (),
+                        _ => return None,
+                    }
+                    cells.push(cell);
+                }
+                if let Some(name) = sheet {
+                    out.push_str(&format!("{}!", name));
+                }
+                out.push_str(&cells.join(":"));
+                rest = &rest[end + 1..];
+                continue;
+            }
+            ';' => {
+                out.push(',');
+                rest = &rest[1..];
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && !"_.$".contains(c))
+                    .unwrap_or(rest.len());
+                let name = &rest[..len];
+                let after = rest[len..].trim_start();
+                if ["TRUE", "FALSE"].contains(&&*name.to_ascii_uppercase())
+                    && after.starts_with("()")
+                {
+                    out.push_str(name);
+                    rest = &after[2..];
+                    continue;
+                }
+                len
+            }
+            c => c.len_utf8(),
+        };
+        out.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TR = Result<(), Error>;
+
+    #[test]
+    fn test_from_openformula() {
+        assert_eq!(
+            from_openformula("of:=SUM([.A1:.$B$2];3)"),
+            "=SUM(A1:$B$2,3)"
+        );
+        assert_eq!(
+            from_openformula("of:=IF(TRUE();[.A1];\"a;[b]\")"),
+            "=if(true,A1,\"a;[b]\")"
+        );
+        assert_eq!(from_openformula("of:=MOD([.A1];2)+5%"), "=A1%2+5/100");
+        assert_eq!(
+            from_openformula("of:=VLOOKUP(1;[.A1:.B3];2)"),
+            "=VLOOKUP(1,A1:B3,2)"
+        );
+        assert_eq!(from_openformula("of:=[$Other.A1]*2"), "=Other!A1*2");
+        assert_eq!(
+            from_openformula("of:=SUM([$'Q1 Sales'.A1:.$B2])"),
+            "=SUM('Q1 Sales'!A1:$B2)"
+        );
+        assert_eq!(
+            from_openformula("of:=SUM([$A.A1:$B.A1])"),
+            "=SUM([$A.A1:$B.A1])"
+        );
+        assert_eq!(
+            from_openformula("of:=ORG.OPENOFFICE.WEEKS([.A1];[.A2];0)"),
+            "=ORG.OPENOFFICE.WEEKS([.A1];[.A2];0)"
+        );
+    }
+
+    #[test]
+    fn test_to_openformula() -> TR {
+        let of = |s: &str| Expr::parse(s).map(|e| to_openformula(&e));
+        assert_eq!(of("SUM(A1:$B$2,3)")?, "SUM([.A1:.$B$2];3)");
+        assert_eq!(
+            of("if(A1%2=0,true,false)")?,
+            "IF(MOD([.A1];2)=0;TRUE();FALSE())"
+        );
+        assert_eq!(of("LOG10(A1)&\"a,b\"")?, "LOG10([.A1])&\"a,b\"");
+        assert_eq!(
+            of("SUM('Q1 Sales'!A1:B2)+Data!$C3")?,
+            "SUM([$'Q1 Sales'.A1:.B2])+[$Data.$C3]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_paragraph() {
+        assert_eq!(paragraph("a  b"), "<text:p>a <text:s/>b</text:p>");
+        assert_eq!(
+            paragraph("  x\t<y>   "),
+            "<text:p><text:s text:c=\"2\"/>x<text:tab/>&lt;y&gt; <text:s text:c=\"2\"/></text:p>"
+        );
+    }
+
+    #[test]
+    fn test_typed_values_round_trip() -> TR {
+        let typed = ["10%", "12.5%", "2024-01-15", "2.50", "007", "-0.10", "3"];
+        let mut sheet = Spreadsheet::new();
+        for (y, contents) in typed.iter().enumerate() {
+            sheet.set(0, y, contents.to_string());
+        }
+        sheet.set(1, 0, "=A1*2".to_string());
+
+        let loaded = &read_ods(&write_ods(&[("Sheet1", &sheet)])?)?[0].1;
+        for (y, contents) in typed.iter().enumerate() {
+            assert_eq!(loaded.raw_cell(0, y), *contents);
+        }
+        assert_eq!(loaded.show_cell(1, 0), "0.2");
+
+        assert_eq!(
+            date_value("2024-01-15T00:00:00").as_deref(),
+            Some("2024-01-15")
+        );
+        assert_eq!(
+            date_value("2024-01-15T12:00:00").as_deref(),
+            Some("45306.5")
+        );
+        assert_eq!(date_value("2024-01-15T10:xx"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_table() -> TR {
+        let xml = "<office:body><table:table table:name=\"Empty\"/>\
+            <table:table table:name=\"Full\"><table:table-row><table:table-cell \
+            office:value-type=\"float\" office:value=\"1\"/></table:table-row>\
+            </table:table></office:body>";
+        let sheets = content(xml)?;
+        let names: Vec<&str> = sheets.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["Empty", "Full"]);
+        assert_eq!(sheets[1].1.raw_cell(0, 0), "1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pretty_printed() -> TR {
+        let xml = "<office:body><table:table table:name=\"S\">
+            <table:table-row>
+              <table:table-cell office:value-type=\"string\">
+                <text:p>one <text:span>two</text:span></text:p>
+                <text:p>three</text:p>
+              </table:table-cell>
+              <table:table-cell office:value-type=\"string\">
+                <text:p/>
+                <text:p>after an empty line</text:p>
+              </table:table-cell>
+            </table:table-row>
+          </table:table></office:body>";
+        let sheets = content(xml)?;
+        assert_eq!(sheets[0].1.raw_cell(0, 0), "one two\nthree");
+        assert_eq!(sheets[0].1.raw_cell(1, 0), "\nafter an empty line");
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> TR {
+        let mut first = Spreadsheet::new();
+        first.set(0, 0, "two  spaces\nand a line".to_string());
+        first.set(3, 0, "=(A3+1)%3".to_string());
+        first.set(0, 2, "4".to_string());
+        first.set(1, 2, "'5".to_string());
+        first.set(2, 2, "=if(A3>3,true,\"no\")".to_string());
+        first.set(3, 2, "=[$Other.A1]+COM.EXAMPLE.F(1;2)".to_string());
+        let second = Spreadsheet::new();
+
+        let bytes = write_ods(&[("First", &first), ("Empty", &second)])?;
+        let sheets = read_ods(&bytes)?;
+        let names: Vec<&str> = sheets.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["First", "Empty"]);
+        let loaded = &sheets[0].1;
+        for y in 0..3 {
+            for x in 0..4 {
+                assert_eq!(loaded.raw_cell(x, y), first.raw_cell(x, y));
+            }
+        }
+        assert_eq!(loaded.show_cell(3, 0), "2");
+        assert_eq!(loaded.get_max_dims(), (3, 2));
+        assert_eq!(sheets[1].1.get_max_dims(), (0, 0));
+        Ok(())
+    }
+
+    // The fixture has repeated cells and rows, a merged cell, a comment, a
+    // styled percentage, a date and a formula from an extension.
+    #[test]
+    fn test_read_fixture() -> TR {
+        let sheets = read_ods(include_bytes!("../../tests/fixtures/budget.ods"))?;
+        assert_eq!(sheets.len(), 2);
+        let (name, budget) = &sheets[0];
+        assert_eq!(name, "Budget");
+        assert_eq!(budget.raw_cell(0, 0), "Item");
+        assert_eq!(budget.raw_cell(1, 1), "1200");
+        assert_eq!(budget.raw_cell(2, 1), "=B2*(1+10/100)");
+        assert_eq!(budget.raw_cell(2, 3), "=B4*(1+10/100)");
+        assert_eq!(budget.raw_cell(1, 4), "=SUM(B2:B4)");
+        assert_eq!(budget.show_cell(1, 4), "1400");
+        assert_eq!(budget.raw_cell(3, 1), "10%");
+        assert_eq!(budget.raw_cell(3, 2), "10%");
+        assert_eq!(budget.raw_cell(0, 5), "'0042");
+        assert_eq!(budget.raw_cell(1, 5), "2024-03-15");
+        assert_eq!(budget.raw_cell(0, 6), "Note  with spaces");
+        assert_eq!(budget.raw_cell(0, 9), "far down");
+        assert_eq!(budget.get_max_dims(), (3, 9));
+
+        let (name, notes) = &sheets[1];
+        assert_eq!(name, "Notes");
+        assert_eq!(notes.raw_cell(0, 0), "TRUE");
+        assert_eq!(notes.raw_cell(0, 1), "=Budget!B5*2");
+        assert_eq!(
+            notes.raw_cell(1, 1),
+            "=ORG.OPENOFFICE.WEEKS(DATE(2024;1;1);[.B3];0)"
+        );
+
+        // Formulas we couldn't translate are written back untouched.
+        let bytes = write_ods(&[("Notes", notes)])?;
+        let again = read_ods(&bytes)?;
+        assert_eq!(again[0].1.raw_cell(1, 1), notes.raw_cell(1, 1));
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 // ```
-// Parsing::new(input).try_one([
-//     |p| Err(Error("oh no, an error!")),
+// Parsing::new(input)?.try_one([
+//     |p| Err(p.error("oh no, an error!".to_string())),
 //     |p| {
 //         let p1 = p.parse_literal()?;
 //         let p2 = p1.skip("+")?.parse_literal()?;
@@ -9,7 +9,10 @@
 // ])?;
 // ```
 
-use std::num;
+mod lexer;
+
+pub use lexer::{tokenize, Token, TokenKind};
+use std::rc::Rc;
 
 // TODO: Reduce number of clones used.
 
@@ -18,9 +21,10 @@ pub struct Parsing<T>
 where
     T: Clone,
 {
-    // TODO: Use a &str with proper lifetime constraint so that I don't have to
-    // copy this left and right.
-    s: String,
+    // Shared between every step of a parse, so that cloning is cheap.
+    tokens: Rc<Vec<Token>>,
+    // The length of the source text, for reporting errors at its end.
+    len: usize,
     i: usize,
     val: T,
 }
@@ -30,12 +34,13 @@ pub type ParseResult<T> = Result<Parsing<T>, Error>;
 pub type Transformer<T1, T2> = fn(Parsing<T1>) -> ParseResult<T2>;
 
 impl Parsing<()> {
-    pub fn new(s: String) -> Parsing<()> {
-        Parsing {
-            s: s,
+    pub fn new(s: &str) -> Result<Parsing<()>, Error> {
+        Ok(Parsing {
+            tokens: Rc::new(lexer::tokenize(s)?),
+            len: s.len(),
             i: 0,
             val: (),
-        }
+        })
     }
 }
 
@@ -46,53 +51,79 @@ impl<T: Clone> Parsing<T> {
 
     pub fn replace<T2: Clone>(self, val: T2) -> Parsing<T2> {
         Parsing {
-            s: self.s.clone(),
+            tokens: self.tokens,
+            len: self.len,
             i: self.i,
-            val: val,
+            val,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.i)
+    }
+
+    // The byte offset of the next token in the source text.
+    pub fn pos(&self) -> usize {
+        self.peek().map_or(self.len, |t| t.start)
+    }
+
+    pub fn error(&self, msg: String) -> Error {
+        Error::new(msg, self.pos())
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(t) => format!("\"{}\"", t.text),
+            None => "end of formula".to_string(),
         }
     }
 
+    // Returns the error of whichever method got furthest, since that's most
+    // likely to be the one the author intended.
     pub fn try_one<T2: Clone>(self, methods: Vec<Transformer<T, T2>>) -> ParseResult<T2> {
+        let mut furthest = self.error(format!("unexpected {}", self.found()));
         for method in methods {
-            let result = method(self.clone());
-            if result.is_ok() {
-                return result;
+            match method(self.clone()) {
+                Ok(p) => return Ok(p),
+                Err(e) if e.pos > furthest.pos => furthest = e,
+                Err(_) => (),
             }
         }
-
-        Err(Error(format!(
-            "No method worked parsing at \"{}\"",
-            self.s.get(self.i..).unwrap_or("")
-        )))
+        Err(furthest)
     }
 
     pub fn skip(mut self, s: &str) -> ParseResult<T> {
-        if self.s[self.i..].starts_with(s) {
-            self.i += s.len();
-            Ok(self)
-        } else {
-            let err = format!(
-                "Expected \"{}\" but found \"{}\" instead",
-                s,
-                self.s[self.i..].to_string()
-            );
-            Err(Error(err))
+        match self.peek() {
+            Some(t) if t.text == s && t.kind != TokenKind::String => {
+                self.i += 1;
+                Ok(self)
+            }
+            _ => Err(self.error(format!("expected \"{}\" but found {}", s, self.found()))),
         }
     }
 
     pub fn parse_int(self) -> ParseResult<i64> {
-        let p = self.match_pred(u8::is_ascii_digit, "is_ascii_digit")?;
-        let x = p.get().parse::<i64>()?;
-        Ok(p.replace(x))
+        let p = self.match_pred(
+            |t| t.kind == TokenKind::Number && t.text.bytes().all(|c| c.is_ascii_digit()),
+            "an integer",
+        )?;
+        match p.get().parse::<i64>() {
+            Ok(x) => Ok(p.replace(x)),
+            Err(e) => Err(Error::new(e.to_string(), p.tokens[p.i - 1].start)),
+        }
+    }
+
+    pub fn ident(self) -> ParseResult<String> {
+        self.match_pred(|t| t.kind == TokenKind::Ident, "a name")
     }
 
     pub fn done(self) -> ParseResult<T> {
-        if self.i == self.s.len() {
+        if self.i == self.tokens.len() {
             Ok(self)
         } else {
-            Err(Error(format!(
-                "expected end of string, instead found \"{}\"",
-                self.s[self.i..].to_string()
+            Err(self.error(format!(
+                "expected the end of the formula, but found {}",
+                self.found()
             )))
         }
     }
@@ -107,37 +138,25 @@ impl<T: Clone> Parsing<T> {
         inner(p)?.skip(right)
     }
 
-    pub fn match_pred(mut self, pred: fn(&u8) -> bool, pred_name: &str) -> ParseResult<String> {
-        let mut offset = 0;
-        let rest_bytes = &self.s.as_bytes()[self.i..];
-        for c in rest_bytes {
-            if !pred(c) {
-                break;
+    // Takes the next token if it matches `pred`, returning its text.
+    pub fn match_pred(mut self, pred: fn(&Token) -> bool, pred_name: &str) -> ParseResult<String> {
+        match self.peek() {
+            Some(t) if pred(t) => {
+                let text = t.text.clone();
+                self.i += 1;
+                Ok(self.replace(text))
             }
-            offset += 1;
-        }
-
-        if offset == 0 {
-            let err = format!(
-                "Expected bytes matching \"{}\", but got \"{}\"",
-                pred_name,
-                &self.s[self.i..],
-            );
-            return Err(Error(err));
+            _ => Err(self.error(format!("expected {} but found {}", pred_name, self.found()))),
         }
-
-        let word = String::from_utf8(rest_bytes[..offset].to_vec())?;
-        self.i += offset;
-        Ok(self.replace(word))
     }
 
-    // One or more repetitions of `once`.
+    // One or more repetitions of `once`. A repetition which fails after
+    // getting past its first token is an error rather than the end of the
+    // list, since it was clearly meant to be part of it.
     pub fn repeat<T2: Clone>(self, once: Transformer<(), T2>) -> ParseResult<Vec<T2>> {
         let mut p = self.drop();
         let mut xs = Vec::new();
         loop {
-            // This is really slow right now because we would copy `self.s`.
-            // Fix is to change `self.s` to a `&str`.
             let pp = once(p.clone());
             match pp {
                 Ok(pp) => {
@@ -145,7 +164,7 @@ impl<T: Clone> Parsing<T> {
                     p = pp.drop();
                 }
                 Err(e) => {
-                    if xs.is_empty() {
+                    if xs.is_empty() || e.pos > p.pos() {
                         return Err(e);
                     } else {
                         break;
@@ -159,20 +178,63 @@ impl<T: Clone> Parsing<T> {
     pub fn drop(self) -> Parsing<()> {
         self.replace(())
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct Error(pub String);
+    // Parses `operand (operator operand)*`, grouping by operator precedence.
+    // All operators are left associative.
+    pub fn climb<O: Operator, E: Clone>(
+        self,
+        operand: Transformer<(), E>,
+        ops: &[O],
+        combine: fn(O, E, E) -> E,
+    ) -> ParseResult<E> {
+        self.drop().climb_above(operand, ops, combine, 0)
+    }
 
-impl From<num::ParseIntError> for Error {
-    fn from(e: num::ParseIntError) -> Error {
-        Error(e.to_string())
+    fn climb_above<O: Operator, E: Clone>(
+        self,
+        operand: Transformer<(), E>,
+        ops: &[O],
+        combine: fn(O, E, E) -> E,
+        min_prec: u8,
+    ) -> ParseResult<E> {
+        let mut p = operand(self.drop())?;
+        loop {
+            let op = match p.peek() {
+                Some(t) if t.kind == TokenKind::Operator => {
+                    ops.iter().find(|op| op.symbol() == t.text)
+                }
+                _ => None,
+            };
+            let op = match op {
+                Some(op) if op.precedence() >= min_prec => *op,
+                _ => return Ok(p),
+            };
+            let lhs = p.get();
+            let rhs =
+                p.skip(op.symbol())?
+                    .climb_above(operand, ops, combine, op.precedence() + 1)?;
+            let e = combine(op, lhs, rhs.get());
+            p = rhs.replace(e);
+        }
     }
 }
 
-impl From<std::string::FromUtf8Error> for Error {
-    fn from(e: std::string::FromUtf8Error) -> Error {
-        Error(e.to_string())
+pub trait Operator: Copy {
+    fn symbol(&self) -> &'static str;
+    // Operators with higher precedence bind more tightly.
+    fn precedence(&self) -> u8;
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub msg: String,
+    // The byte offset in the source text where things went wrong.
+    pub pos: usize,
+}
+
+impl Error {
+    pub fn new(msg: String, pos: usize) -> Error {
+        Error { msg, pos }
     }
 }
 
@@ -184,49 +246,128 @@ mod tests {
 
     #[test]
     fn test_skip() -> TR {
-        let p = Parsing::new("foo".to_string()).skip("fo")?;
-        assert_eq!(p.i, 2);
-        assert_eq!(p.s, "foo");
+        let p = Parsing::new("foo bar")?.skip("foo")?;
+        assert_eq!(p.i, 1);
+        assert_eq!(p.peek().unwrap().text, "bar");
         Ok(())
     }
 
+    #[test]
+    fn test_skip_error_position() {
+        let p = Parsing::new("foo  bar").unwrap().skip("foo").unwrap();
+        assert_eq!(p.skip("baz").err().unwrap().pos, 5);
+    }
+
     #[test]
     fn test_parse_int_respects_end_of_string() -> TR {
-        let p = Parsing::new("123".to_string()).parse_int()?;
+        let p = Parsing::new("123")?.parse_int()?;
         assert_eq!(p.val, 123);
-        assert_eq!(p.i, 3);
+        assert_eq!(p.i, 1);
         Ok(())
     }
 
     #[test]
     fn test_parse_int_respects_alpha_chars() -> TR {
-        let p = Parsing::new("456foo".to_string()).parse_int()?;
+        let p = Parsing::new("456foo")?.parse_int()?;
         assert_eq!(p.val, 456);
-        assert_eq!(p.i, 3);
+        assert_eq!(p.i, 1);
         Ok(())
     }
 
+    #[test]
+    fn test_parse_int_rejects_fractions() {
+        assert!(Parsing::new("1.5").unwrap().parse_int().is_err());
+    }
+
     #[test]
     fn test_done() -> TR {
-        let p = Parsing::new("123".to_string()).parse_int()?.done()?;
+        let p = Parsing::new(" 123 ")?.parse_int()?.done()?;
         assert_eq!(p.val, 123);
-        assert_eq!(p.i, 3);
+        assert_eq!(p.i, 1);
         Ok(())
     }
 
     #[test]
     fn test_match_pred() -> TR {
-        let p = Parsing::new("abcdf".to_string())
-            .match_pred(|c| (*c as char) < 'd', "c < 'd'")?;
+        let p = Parsing::new("abc d")?.match_pred(|t| t.text.len() == 3, "three letters")?;
         assert_eq!(p.val, "abc");
+        assert!(p
+            .match_pred(|t| t.text.len() == 3, "three letters")
+            .is_err());
         Ok(())
     }
 
     #[test]
     fn test_repeat() -> TR {
-        let p = Parsing::new("a, a, b, c".to_string()).repeat(|p| p.skip("a, "))?;
+        let p = Parsing::new("a, a, b, c")?.repeat(|p| p.skip("a")?.skip(","))?;
         assert_eq!(p.val.len(), 2);
-        assert_eq!(p.i, 6);
+        assert_eq!(p.i, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeat_fails_part_way() {
+        let p = Parsing::new("a, a, a b").unwrap();
+        let err = p.repeat(|p| p.skip("a")?.skip(",")).err().unwrap();
+        assert_eq!(err.pos, 8);
+    }
+
+    #[test]
+    fn test_try_one_reports_furthest_error() {
+        let err = Parsing::new("a b c")
+            .unwrap()
+            .try_one(vec![|p| p.skip("x"), |p| p.skip("a")?.skip("b")?.skip("x")])
+            .err()
+            .unwrap();
+        assert_eq!(err.pos, 4);
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Op {
+        Add,
+        Mul,
+        Lt,
+        Le,
+    }
+
+    impl Operator for Op {
+        fn symbol(&self) -> &'static str {
+            match self {
+                Op::Add => "+",
+                Op::Mul => "*",
+                Op::Lt => "<",
+                Op::Le => "<=",
+            }
+        }
+
+        fn precedence(&self) -> u8 {
+            match self {
+                Op::Lt | Op::Le => 1,
+                Op::Add => 2,
+                Op::Mul => 3,
+            }
+        }
+    }
+
+    fn climb(s: &str) -> Result<String, Error> {
+        let ops = [Op::Add, Op::Mul, Op::Lt, Op::Le];
+        let p = Parsing::new(s)?.climb(
+            |p| p.match_pred(|t| t.kind == TokenKind::Number, "a number"),
+            &ops,
+            |op, x, y| format!("({}{}{})", x, op.symbol(), y),
+        )?;
+        Ok(p.done()?.get())
+    }
+
+    #[test]
+    fn test_climb() -> TR {
+        assert_eq!(climb("1")?, "1");
+        assert_eq!(climb("1+2*3")?, "(1+(2*3))");
+        assert_eq!(climb("1*2+3")?, "((1*2)+3)");
+        assert_eq!(climb("1+2+3")?, "((1+2)+3)");
+        assert_eq!(climb("1+2<=3*4<5")?, "(((1+2)<=(3*4))<5)");
+        assert_eq!(climb(" 1 +  2 * 3 ")?, "(1+(2*3))");
+        assert!(climb("1+").is_err());
         Ok(())
     }
 
@@ -234,7 +375,7 @@ mod tests {
 
     #[test]
     fn test_name() -> TR {
-        let p = Parsing::new("".to_string())?;
+        let p = Parsing::new("")?;
         Ok(())
     }
 
@@ -0,0 +1,251 @@
+// Splits formula text into tokens for `Parsing`, dropping whitespace.
+//
+// Every token remembers the byte span it came from, so that parse errors can
+// point at a column.
+
+use super::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    // Names of functions and cells, like `SUM`, `B12` or `$A$1`.
+    Ident,
+    // Unsigned numeric literals, like `12`, `1.5` or `2e10`.
+    Number,
+    // Quoted text. The token's text includes the quotes.
+    String,
+    // A sheet name in single quotes, for names which aren't identifiers,
+    // like `'Q1 Sales'`. The token's text includes the quotes.
+    SheetName,
+    // Spreadsheet error values, like `#DIV/0!` or `#N/A`.
+    ErrorCode,
+    Operator,
+    // Brackets and separators.
+    Punct,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    // The byte span of the token in the source text.
+    pub start: usize,
+    pub end: usize,
+}
+
+// Longer operators come first so that "<=" isn't read as "<".
+const OPERATORS: [&str; 13] = [
+    "<=", ">=", "<>", "+", "-", "*", "/", "%", "^", "=", "<", ">", "&",
+];
+const PUNCTUATION: [char; 5] = ['(', ')', ',', ':', '!'];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '$'
+}
+
+pub fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        let c = rest.chars().next().unwrap();
+
+        let (kind, len) = if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        } else if is_ident_start(c) {
+            let len = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+            (TokenKind::Ident, len)
+        } else if c.is_ascii_digit() || (c == '.' && starts_with_digit(&rest[1..])) {
+            (TokenKind::Number, number_len(rest))
+        } else if c == '#' {
+            (TokenKind::ErrorCode, error_code_len(rest))
+        } else if c == '"' {
+            let len = quoted_len(rest, '"')
+                .ok_or_else(|| Error::new("unterminated string".to_string(), i))?;
+            (TokenKind::String, len)
+        } else if c == '\'' {
+            let len = quoted_len(rest, '\'')
+                .ok_or_else(|| Error::new("unterminated sheet name".to_string(), i))?;
+            (TokenKind::SheetName, len)
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            (TokenKind::Operator, op.len())
+        } else if PUNCTUATION.contains(&c) {
+            (TokenKind::Punct, 1)
+        } else {
+            return Err(Error::new(format!("unexpected character '{}'", c), i));
+        };
+
+        tokens.push(Token {
+            kind,
+            text: rest[..len].to_string(),
+            start: i,
+            end: i + len,
+        });
+        i += len;
+    }
+    Ok(tokens)
+}
+
+fn starts_with_digit(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn digits_len(s: &str) -> usize {
+    s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len())
+}
+
+// Matches `digits [. digits] [e [+-] digits]`. The exponent is only taken if
+// it has digits, so that "2e" lexes as a number followed by a name.
+fn number_len(s: &str) -> usize {
+    let mut len = digits_len(s);
+    if s[len..].starts_with('.') {
+        len += 1 + digits_len(&s[len + 1..]);
+    }
+    let rest = &s[len..];
+    if rest.starts_with(['e', 'E']) {
+        let sign = if rest[1..].starts_with(['+', '-']) {
+            1
+        } else {
+            0
+        };
+        let exp = digits_len(&rest[1 + sign..]);
+        if exp > 0 {
+            len += 1 + sign + exp;
+        }
+    }
+    len
+}
+
+// Matches "#" followed by letters, digits and slashes, and then optionally a
+// "!" or "?".
+fn error_code_len(s: &str) -> usize {
+    let len = 1 + s[1..]
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '/')
+        .unwrap_or(s.len() - 1);
+    match s[len..].starts_with(['!', '?']) {
+        true => len + 1,
+        false => len,
+    }
+}
+
+// Matches text between two `quote` characters. A doubled quote inside stands
+// for a single quote character.
+fn quoted_len(s: &str, quote: char) -> Option<usize> {
+    let mut i = 1;
+    loop {
+        let j = s[i..].find(quote)?;
+        match s[i + j + 1..].starts_with(quote) {
+            true => i += j + 2,
+            false => return Some(i + j + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(s: &str) -> Vec<(TokenKind, String)> {
+        tokenize(s)
+            .unwrap()
+            .into_iter()
+            .map(|t| (t.kind, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        use TokenKind::*;
+        assert_eq!(
+            lex(" SUM( $A$1 : b2 ,3)<=\"x\""),
+            vec![
+                (Ident, "SUM".to_string()),
+                (Punct, "(".to_string()),
+                (Ident, "$A$1".to_string()),
+                (Punct, ":".to_string()),
+                (Ident, "b2".to_string()),
+                (Punct, ",".to_string()),
+                (Number, "3".to_string()),
+                (Punct, ")".to_string()),
+                (Operator, "<=".to_string()),
+                (String, "\"x\"".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = tokenize("( 1 +22 )").unwrap();
+        let spans: Vec<(usize, usize)> = tokens.iter().map(|t| (t.start, t.end)).collect();
+        assert_eq!(spans, vec![(0, 1), (2, 3), (4, 5), (5, 7), (8, 9)]);
+    }
+
+    #[test]
+    fn test_numbers() {
+        use TokenKind::*;
+        assert_eq!(lex("1.5"), vec![(Number, "1.5".to_string())]);
+        assert_eq!(lex(".5"), vec![(Number, ".5".to_string())]);
+        assert_eq!(lex("2E-3"), vec![(Number, "2E-3".to_string())]);
+        assert_eq!(
+            lex("2e"),
+            vec![(Number, "2".to_string()), (Ident, "e".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        use TokenKind::*;
+        assert_eq!(
+            lex("\"a \"\"b\"\"\"&\"\""),
+            vec![
+                (String, "\"a \"\"b\"\"\"".to_string()),
+                (Operator, "&".to_string()),
+                (String, "\"\"".to_string()),
+            ]
+        );
+        assert_eq!(tokenize("1+\"abc").unwrap_err().pos, 2);
+    }
+
+    #[test]
+    fn test_sheet_names() {
+        use TokenKind::*;
+        assert_eq!(
+            lex("'Q1 ''24'!A1+Data!B2"),
+            vec![
+                (SheetName, "'Q1 ''24'".to_string()),
+                (Punct, "!".to_string()),
+                (Ident, "A1".to_string()),
+                (Operator, "+".to_string()),
+                (Ident, "Data".to_string()),
+                (Punct, "!".to_string()),
+                (Ident, "B2".to_string()),
+            ]
+        );
+        assert_eq!(tokenize("'Q1!A1").unwrap_err().pos, 0);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        use TokenKind::*;
+        assert_eq!(
+            lex("#DIV/0!+#N/A&#NAME?"),
+            vec![
+                (ErrorCode, "#DIV/0!".to_string()),
+                (Operator, "+".to_string()),
+                (ErrorCode, "#N/A".to_string()),
+                (Operator, "&".to_string()),
+                (ErrorCode, "#NAME?".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_character() {
+        let err = tokenize("1 + ~").unwrap_err();
+        assert_eq!(err.pos, 4);
+    }
+}
@@ -0,0 +1,1054 @@
+// A workbook of named sheets, whose formulas can refer to each other's cells
+// as `Data!A1` or `'Q1 Sales'!A1:B2`.
+//
+// Each sheet keeps its own cells and the dependencies between them, just as
+// it does on its own. The workbook adds the references between sheets: it
+// copies the values of the cells a sheet's formulas refer to into that sheet,
+// and recalculates across sheets in a single topological order, so that a
+// cycle through several sheets is found like any other. Sheet names are
+// matched without regard to case.
+//
+// Names defined in the workbook, like `TaxRate`, stand for a cell, a range
+// or a constant in any sheet's formulas. A formula using a name depends on
+// the cells the name stands for, just as if it referred to them itself.
+//
+// Every change to the workbook can be undone, including loading another
+// workbook over it. Each sheet keeps the history of its own cells, and the
+// workbook records which sheets each of its steps touched, along with the
+// changes to the sheets themselves and to names.
+
+use super::history;
+use super::{
+    is_address_like, Axis, Cell, CellRange, CellRef, Edit, Error, Expr, Spreadsheet, Value,
+};
+use std::collections::{HashMap, HashSet};
+
+// A cell on one of the workbook's sheets, by the sheet's position.
+type Node = (usize, CellRef);
+
+// For each sheet, by position, the ranges on it which formulas on any sheet
+// refer to by the sheet's name, directly or through names, along with the
+// cell referring to each. A range referred to twice is there twice.
+type SheetRefIndex = Vec<Vec<(CellRange, Node)>>;
+
+// How many steps can be undone. A step makes at most two steps on any one
+// sheet, when restructuring it rewrites its references to itself, so with
+// this many, and the step being made, the sheets never forget a step which
+// the workbook could still undo.
+const MAX_STEPS: usize = history::MAX_STEPS / 2 - 1;
+
+pub struct Workbook {
+    sheets: Vec<(String, Spreadsheet)>,
+    // The position of the sheet that cell edits apply to.
+    current: usize,
+    // Each name with what it stands for: a reference to a cell or range on
+    // one of the sheets, or a constant expression.
+    names: Vec<(String, Expr)>,
+    // The steps which can be undone and redone, and the changes made so far
+    // in the step being made.
+    done: Vec<Vec<Change>>,
+    undone: Vec<Vec<Change>>,
+    step: Vec<Change>,
+}
+
+// A change to a workbook, holding what's needed to undo and redo it.
+enum Change {
+    // A step in the history of the sheet at that position.
+    Step(usize),
+    // A sheet added or deleted at a position. The sheet is kept here while
+    // it's out of the workbook, so both undoing and redoing swap it in or out.
+    Sheet(usize, Option<(String, Box<Spreadsheet>)>),
+    // A sheet renamed, holding its other name.
+    Rename(usize, String),
+    // A sheet's contents replaced, holding its other contents.
+    Replace(usize, Box<Spreadsheet>),
+    // Names defined, deleted or rewritten, holding the other set of names.
+    Names(Vec<(String, Expr)>),
+    // The whole workbook replaced, holding the other sheets, current sheet
+    // and names.
+    Book(Box<Contents>),
+}
+
+type Contents = (Vec<(String, Spreadsheet)>, usize, Vec<(String, Expr)>);
+
+impl Default for Workbook {
+    fn default() -> Workbook {
+        Workbook::new()
+    }
+}
+
+impl Workbook {
+    pub fn new() -> Workbook {
+        Workbook {
+            sheets: vec![("Sheet1".to_string(), Spreadsheet::new())],
+            current: 0,
+            names: vec![],
+            done: vec![],
+            undone: vec![],
+            step: vec![],
+        }
+    }
+
+    // Makes a workbook of sheets read from a file, with the first current.
+    pub fn from_sheets(sheets: Vec<(String, Spreadsheet)>) -> Result<Workbook, Error> {
+        if sheets.is_empty() {
+            return Err(Error::DescriptiveError(
+                "a workbook needs at least one sheet".to_string(),
+            ));
+        }
+        let mut book = Workbook {
+            sheets: vec![],
+            current: 0,
+            names: vec![],
+            done: vec![],
+            undone: vec![],
+            step: vec![],
+        };
+        for (name, sheet) in sheets {
+            book.check_name(&name, None)?;
+            book.sheets.push((name, sheet));
+        }
+        book.recalculate_all();
+        Ok(book)
+    }
+
+    // The current sheet.
+    pub fn sheet(&self) -> &Spreadsheet {
+        &self.sheets[self.current].1
+    }
+
+    pub fn sheet_name(&self) -> &str {
+        &self.sheets[self.current].0
+    }
+
+    // Every sheet with its name, in order.
+    pub fn sheets(&self) -> Vec<(&str, &Spreadsheet)> {
+        self.sheets.iter().map(|(n, s)| (n.as_str(), s)).collect()
+    }
+
+    // Adds an empty sheet after the others.
+    pub fn add_sheet(&mut self, name: &str) -> Result<(), Error> {
+        self.check_name(name, None)?;
+        self.sheets.push((name.to_string(), Spreadsheet::new()));
+        self.step.push(Change::Sheet(self.sheets.len() - 1, None));
+        // Formulas may have referred to the sheet before it existed.
+        let changed = self.depending_on_sheet(name);
+        self.recalculate(&changed);
+        self.commit();
+        Ok(())
+    }
+
+    // Formulas referring to the sheet are rewritten to use its new name.
+    pub fn rename_sheet(&mut self, name: &str, new_name: &str) -> Result<(), Error> {
+        let i = self.find(name)?;
+        self.check_name(new_name, Some(i))?;
+        let old_name = std::mem::replace(&mut self.sheets[i].0, new_name.to_string());
+        self.step.push(Change::Rename(i, old_name));
+        let mut changed = self.rewrite_sheet_refs(name, |_, r| {
+            Expr::SheetRef(new_name.to_string(), Box::new(r.clone()))
+        });
+        changed.extend(self.depending_on_sheet(new_name));
+        self.recalculate(&changed);
+        self.commit();
+        Ok(())
+    }
+
+    pub fn switch_sheet(&mut self, name: &str) -> Result<(), Error> {
+        self.current = self.find(name)?;
+        Ok(())
+    }
+
+    // References to the deleted sheet's cells become #REF! errors.
+    pub fn delete_sheet(&mut self, name: &str) -> Result<(), Error> {
+        let i = self.find(name)?;
+        if self.sheets.len() == 1 {
+            return Err(Error::DescriptiveError(
+                "can't delete the only sheet".to_string(),
+            ));
+        }
+        let mut slot = None;
+        self.swap_sheet(i, &mut slot);
+        self.step.push(Change::Sheet(i, slot));
+        let changed = self.rewrite_sheet_refs(name, |_, _| Expr::Error(Error::RefError));
+        self.recalculate(&changed);
+        self.commit();
+        Ok(())
+    }
+
+    // Defines a name for formulas to use in place of a cell, a range or a
+    // constant, like "Sheet2!B1", "B2:B9" or "0.2", replacing any definition
+    // it had. A reference without a sheet name is to the current sheet.
+    pub fn define_name(&mut self, name: &str, definition: &str) -> Result<(), Error> {
+        check_defined_name(name)?;
+        let text = definition.strip_prefix('=').unwrap_or(definition);
+        let definition = match Expr::parse(text)? {
+            e @ (Expr::CellRef(_) | Expr::Range(..)) => {
+                Expr::SheetRef(self.sheet_name().to_string(), Box::new(e))
+            }
+            e @ Expr::SheetRef(..) => e,
+            e => {
+                let mut refers = false;
+                e.visit_refs(&mut |_| refers = true);
+                if refers {
+                    return Err(Error::DescriptiveError(
+                        "a name can only stand for a cell, a range or a constant".to_string(),
+                    ));
+                }
+                e
+            }
+        };
+        self.step.push(Change::Names(self.names.clone()));
+        match self.names.iter().position(|(n, _)| same_name(n, name)) {
+            Some(i) => self.names[i] = (name.to_string(), definition),
+            None => self.names.push((name.to_string(), definition)),
+        }
+        let changed = self.using_name(name);
+        self.recalculate(&changed);
+        self.commit();
+        Ok(())
+    }
+
+    // Formulas using a deleted name give #NAME? errors.
+    pub fn delete_name(&mut self, name: &str) -> Result<(), Error> {
+        let i = self
+            .names
+            .iter()
+            .position(|(n, _)| same_name(n, name))
+            .ok_or_else(|| Error::DescriptiveError(format!("there is no name \"{}\"", name)))?;
+        self.step.push(Change::Names(self.names.clone()));
+        self.names.remove(i);
+        let changed = self.using_name(name);
+        self.recalculate(&changed);
+        self.commit();
+        Ok(())
+    }
+
+    // Every name with its definition as formula text, in the order they were
+    // defined.
+    pub fn names(&self) -> Vec<(&str, String)> {
+        self.names
+            .iter()
+            .map(|(n, definition)| (n.as_str(), definition.to_string()))
+            .collect()
+    }
+
+    // Replaces the contents of the current sheet, as when loading a file
+    // into it.
+    pub fn replace_sheet(&mut self, sheet: Spreadsheet) {
+        let old = std::mem::replace(&mut self.sheets[self.current].1, sheet);
+        self.step.push(Change::Replace(self.current, Box::new(old)));
+        self.recalculate_all();
+        self.commit();
+    }
+
+    // Replaces the whole workbook with another, as when loading a workbook
+    // file. The other workbook's history is dropped.
+    pub fn replace_all(&mut self, book: Workbook) {
+        let mut contents = Box::new((book.sheets, book.current, book.names));
+        self.swap_contents(&mut contents);
+        self.step.push(Change::Book(contents));
+        self.recalculate_all();
+        self.commit();
+    }
+
+    // Setting a cell to "" clears it.
+    pub fn set(&mut self, x: usize, y: usize, contents: String) {
+        self.edit_sheet(self.current, |sheet| sheet.set(x, y, contents));
+        self.recalculate(&[(self.current, CellRef(x, y))]);
+        self.commit();
+    }
+
+    pub fn clear(&mut self, x: usize, y: usize) {
+        self.set(x, y, String::new());
+    }
+
+    pub fn clear_range(&mut self, from: (usize, usize), to: (usize, usize)) {
+        let range = CellRange(
+            CellRef(from.0.min(to.0), from.1.min(to.1)),
+            CellRef(from.0.max(to.0), from.1.max(to.1)),
+        );
+        let s = self.current;
+        let cleared: Vec<Node> = self.sheets[s]
+            .1
+            .cells
+            .keys()
+            .filter(|r| range.contains(**r))
+            .map(|r| (s, *r))
+            .collect();
+        self.edit_sheet(s, |sheet| sheet.clear_range(from, to));
+        self.recalculate(&cleared);
+        self.commit();
+    }
+
+    pub fn copy_cell(&mut self, src: (usize, usize), dst: (usize, usize)) -> Result<(), Error> {
+        self.edit_sheet(self.current, |sheet| sheet.copy_cell(src, dst))?;
+        self.recalculate(&[(self.current, CellRef(dst.0, dst.1))]);
+        self.commit();
+        Ok(())
+    }
+
+    pub fn insert_rows(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Insert(Axis::Row, at, n));
+    }
+
+    pub fn delete_rows(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Delete(Axis::Row, at, n));
+    }
+
+    pub fn insert_cols(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Insert(Axis::Col, at, n));
+    }
+
+    pub fn delete_cols(&mut self, at: usize, n: usize) {
+        self.restructure(Edit::Delete(Axis::Col, at, n));
+    }
+
+    // Restructures the current sheet, and rewrites the references to its
+    // cells from every sheet to follow them.
+    fn restructure(&mut self, edit: Edit) {
+        let s = self.current;
+        self.edit_sheet(s, |sheet| sheet.restructure(edit));
+        let name = self.sheets[s].0.clone();
+        let mut changed = self.rewrite_sheet_refs(&name, |n, r| match r.adjust(edit) {
+            Expr::Error(e) => Expr::Error(e),
+            r => Expr::SheetRef(n.to_string(), Box::new(r)),
+        });
+        changed.extend(self.sheets[s].1.cells.keys().map(|r| (s, *r)));
+        self.recalculate(&changed);
+        self.commit();
+    }
+
+    // Takes back the latest step which hasn't been undone, returning whether
+    // there was one.
+    pub fn undo(&mut self) -> bool {
+        let mut step = match self.done.pop() {
+            Some(step) => step,
+            None => return false,
+        };
+        let mut changed = Some(vec![]);
+        for change in step.iter_mut().rev() {
+            changed = changed.zip(self.run(change, true)).map(join);
+        }
+        self.undone.push(step);
+        self.recalculate_changed(changed);
+        true
+    }
+
+    // Makes the latest undone step again, returning whether there was one.
+    pub fn redo(&mut self) -> bool {
+        let mut step = match self.undone.pop() {
+            Some(step) => step,
+            None => return false,
+        };
+        let mut changed = Some(vec![]);
+        for change in step.iter_mut() {
+            changed = changed.zip(self.run(change, false)).map(join);
+        }
+        self.done.push(step);
+        self.recalculate_changed(changed);
+        true
+    }
+
+    // Runs a change one way or the other, returning the cells whose values
+    // it may have changed, or None if it may have changed any cell.
+    fn run(&mut self, change: &mut Change, undo: bool) -> Option<Vec<Node>> {
+        match change {
+            Change::Step(s) => {
+                let sheet = &mut self.sheets[*s].1;
+                sheet.deferring = true;
+                let changed = match undo {
+                    true => sheet.undo_step(),
+                    false => sheet.redo_step(),
+                };
+                sheet.deferring = false;
+                let s = *s;
+                Some(changed.into_iter().flatten().map(|r| (s, r)).collect())
+            }
+            // The formulas referring to the sheet by either name.
+            Change::Rename(i, name) => {
+                let mut changed = self.referring_to(name);
+                changed.extend(self.referring_to(&self.sheets[*i].0));
+                std::mem::swap(&mut self.sheets[*i].0, name);
+                Some(changed)
+            }
+            // The formulas using the names in either set.
+            Change::Names(names) => {
+                let mut changed = vec![];
+                for (n, _) in names.iter().chain(&self.names) {
+                    changed.extend(self.using_name(n));
+                }
+                std::mem::swap(&mut self.names, names);
+                Some(changed)
+            }
+            Change::Sheet(i, slot) => {
+                self.swap_sheet(*i, slot);
+                None
+            }
+            Change::Replace(i, sheet) => {
+                std::mem::swap(&mut self.sheets[*i].1, sheet);
+                None
+            }
+            Change::Book(contents) => {
+                self.swap_contents(contents);
+                None
+            }
+        }
+    }
+
+    fn recalculate_changed(&mut self, changed: Option<Vec<Node>>) {
+        match changed {
+            Some(changed) => self.recalculate(&changed),
+            None => self.recalculate_all(),
+        }
+    }
+
+    fn swap_contents(&mut self, contents: &mut Contents) {
+        std::mem::swap(&mut self.sheets, &mut contents.0);
+        std::mem::swap(&mut self.current, &mut contents.1);
+        std::mem::swap(&mut self.names, &mut contents.2);
+    }
+
+    // Puts the sheet in `slot` into the workbook at position `i`, or if there
+    // isn't one, takes the sheet at `i` out into `slot`.
+    fn swap_sheet(&mut self, i: usize, slot: &mut Option<(String, Box<Spreadsheet>)>) {
+        match slot.take() {
+            Some((name, sheet)) => {
+                self.sheets.insert(i, (name, *sheet));
+                if self.current >= i && self.sheets.len() > 1 {
+                    self.current += 1;
+                }
+            }
+            None => {
+                let (name, sheet) = self.sheets.remove(i);
+                *slot = Some((name, Box::new(sheet)));
+                if self.current > i || self.current == self.sheets.len() {
+                    self.current -= 1;
+                }
+            }
+        }
+    }
+
+    // Runs `f` on the sheet at position `s`, making any step it adds to the
+    // sheet's history part of the workbook's step. The sheet leaves the
+    // cells `f` changes to be recalculated by the workbook.
+    fn edit_sheet<T>(&mut self, s: usize, f: impl FnOnce(&mut Spreadsheet) -> T) -> T {
+        let sheet = &mut self.sheets[s].1;
+        let steps = sheet.steps();
+        sheet.deferring = true;
+        let out = f(sheet);
+        sheet.deferring = false;
+        if sheet.steps() > steps {
+            self.step.push(Change::Step(s));
+        }
+        out
+    }
+
+    // Ends the step being made, so that undoing takes it back as a whole.
+    fn commit(&mut self) {
+        if !self.step.is_empty() {
+            self.done.push(std::mem::take(&mut self.step));
+            self.undone.clear();
+            if self.done.len() > MAX_STEPS {
+                self.done.remove(0);
+            }
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.sheets.iter().position(|(n, _)| same_name(n, name))
+    }
+
+    fn find(&self, name: &str) -> Result<usize, Error> {
+        self.position(name)
+            .ok_or_else(|| Error::DescriptiveError(format!("there is no sheet named \"{}\"", name)))
+    }
+
+    // Sheet names can't be blank or the same as another sheet's.
+    fn check_name(&self, name: &str, renaming: Option<usize>) -> Result<(), Error> {
+        if name.trim().is_empty() {
+            return Err(Error::DescriptiveError(
+                "a sheet name can't be blank".to_string(),
+            ));
+        }
+        match self.position(name) {
+            Some(i) if Some(i) != renaming => Err(Error::DescriptiveError(format!(
+                "there is already a sheet named \"{}\"",
+                self.sheets[i].0
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // The cells whose formulas refer to the named sheet.
+    fn referring_to(&self, name: &str) -> Vec<Node> {
+        let mut out = vec![];
+        for (s, (_, sheet)) in self.sheets.iter().enumerate() {
+            out.extend(
+                sheet
+                    .sheet_refs
+                    .iter()
+                    .filter(|(n, _, _)| same_name(n, name))
+                    .map(|(_, _, c)| (s, *c)),
+            );
+        }
+        out
+    }
+
+    // The cells whose formulas use the name.
+    fn using_name(&self, name: &str) -> Vec<Node> {
+        let name = name.to_lowercase();
+        let mut out = vec![];
+        for (s, (_, sheet)) in self.sheets.iter().enumerate() {
+            out.extend(
+                sheet
+                    .name_refs
+                    .iter()
+                    .filter(|(n, _)| *n == name)
+                    .map(|(_, c)| (s, *c)),
+            );
+        }
+        out
+    }
+
+    // The cells whose formulas refer to the named sheet, directly or through
+    // names.
+    fn depending_on_sheet(&self, name: &str) -> Vec<Node> {
+        let mut out = self.referring_to(name);
+        for (n, definition) in &self.names {
+            if definition
+                .sheet_refs()
+                .iter()
+                .any(|(s, _)| same_name(s, name))
+            {
+                out.extend(self.using_name(n));
+            }
+        }
+        out
+    }
+
+    fn definition(&self, name: &str) -> Option<&Expr> {
+        self.names
+            .iter()
+            .find(|(n, _)| same_name(n, name))
+            .map(|(_, definition)| definition)
+    }
+
+    // Replaces every reference to the named sheet, in formulas and in names'
+    // definitions, with what `f` makes of the name it's written with and the
+    // inner reference. Returns the cells whose formulas were rewritten or
+    // use a name which was.
+    fn rewrite_sheet_refs(&mut self, name: &str, f: impl Fn(&str, &Expr) -> Expr) -> Vec<Node> {
+        let rewrite = |e: &Expr| {
+            e.map_sheet_refs(&|n, r| match same_name(n, name) {
+                true => f(n, r),
+                false => Expr::SheetRef(n.to_string(), Box::new(r.clone())),
+            })
+        };
+        let mut cells = self.referring_to(name);
+        cells.sort_by_key(|(s, r)| (*s, r.1, r.0));
+        cells.dedup();
+        for s in 0..self.sheets.len() {
+            let formulas: Vec<(CellRef, String)> = cells
+                .iter()
+                .filter(|(t, _)| *t == s)
+                .filter_map(|(_, r)| match self.sheets[s].1.cell(r.0, r.1) {
+                    Some(Cell { expr: Ok(e), .. }) => Some((*r, format!("={}", rewrite(e)))),
+                    _ => None,
+                })
+                .collect();
+            if !formulas.is_empty() {
+                self.edit_sheet(s, |sheet| sheet.relink(formulas));
+            }
+        }
+
+        let old_names = self.names.clone();
+        let mut rewritten = vec![];
+        for (n, definition) in &mut self.names {
+            if definition
+                .sheet_refs()
+                .iter()
+                .any(|(s, _)| same_name(s, name))
+            {
+                *definition = rewrite(definition);
+                rewritten.push(n.clone());
+            }
+        }
+        if !rewritten.is_empty() {
+            self.step.push(Change::Names(old_names));
+        }
+        for n in rewritten {
+            cells.extend(self.using_name(&n));
+        }
+        cells
+    }
+
+    // What the formula in `node` refers to on any sheet, by sheet name,
+    // directly and through the names it uses.
+    fn sheet_refs_of(&self, (s, cell): Node) -> Vec<(String, CellRange)> {
+        let c = match self.sheets[s].1.cell(cell.0, cell.1) {
+            Some(c) => c,
+            None => return vec![],
+        };
+        let mut out = c.sheet_refs.clone();
+        for name in &c.names {
+            out.extend(
+                self.definition(name)
+                    .map_or_else(Vec::new, Expr::sheet_refs),
+            );
+        }
+        out
+    }
+
+    fn sheet_ref_index(&self) -> SheetRefIndex {
+        let mut index = vec![vec![]; self.sheets.len()];
+        for (t, (_, sheet)) in self.sheets.iter().enumerate() {
+            for (name, r, c) in &sheet.sheet_refs {
+                if let Some(s) = self.position(name) {
+                    index[s].push((*r, (t, *c)));
+                }
+            }
+        }
+        for (n, definition) in &self.names {
+            let users = self.using_name(n);
+            for (name, r) in definition.sheet_refs() {
+                if let Some(s) = self.position(&name) {
+                    index[s].extend(users.iter().map(|u| (r, *u)));
+                }
+            }
+        }
+        index
+    }
+
+    // The cells on any sheet whose formulas refer to `node`, once for each
+    // reference or range that covers it.
+    fn referrers(&self, index: &SheetRefIndex, (s, cell): Node) -> Vec<Node> {
+        let mut out: Vec<Node> = self.sheets[s]
+            .1
+            .referrers(cell)
+            .into_iter()
+            .map(|c| (s, c))
+            .collect();
+        out.extend(
+            index[s]
+                .iter()
+                .filter(|(r, _)| r.contains(cell))
+                .map(|(_, node)| *node),
+        );
+        out
+    }
+
+    // Returns the cells in `start` and every cell on any sheet that
+    // transitively refers to them.
+    fn dependents(&self, index: &SheetRefIndex, start: &[Node]) -> Vec<Node> {
+        let mut seen: HashSet<Node> = start.iter().copied().collect();
+        let mut stack: Vec<Node> = seen.iter().copied().collect();
+        let mut out = vec![];
+        while let Some(node) = stack.pop() {
+            out.push(node);
+            for b in self.referrers(index, node) {
+                if seen.insert(b) {
+                    stack.push(b);
+                }
+            }
+        }
+        out
+    }
+
+    fn recalculate_all(&mut self) {
+        let all: Vec<Node> = self
+            .sheets
+            .iter()
+            .enumerate()
+            .flat_map(|(s, (_, sheet))| sheet.cells.keys().map(move |r| (s, *r)))
+            .collect();
+        self.recalculate(&all);
+    }
+
+    // Like `Spreadsheet::recalculate`, but following references between
+    // sheets too.
+    fn recalculate(&mut self, changed: &[Node]) {
+        let index = self.sheet_ref_index();
+        let nodes = self.dependents(&index, changed);
+        // The members on each sheet, by the sheet's position.
+        let mut members: Vec<HashSet<CellRef>> = vec![HashSet::new(); self.sheets.len()];
+        for (s, cell) in &nodes {
+            members[*s].insert(*cell);
+        }
+        // How many members are in a range, going through whichever of the
+        // range and the members on its sheet is smaller.
+        let covered = |t: usize, r: &CellRange| {
+            let on_sheet = &members[t];
+            let area = (r.1 .0 - r.0 .0 + 1).saturating_mul(r.1 .1 - r.0 .1 + 1);
+            match area <= on_sheet.len() {
+                true => (r.0 .1..=r.1 .1)
+                    .flat_map(|y| (r.0 .0..=r.1 .0).map(move |x| CellRef(x, y)))
+                    .filter(|c| on_sheet.contains(c))
+                    .count(),
+                false => on_sheet.iter().filter(|c| r.contains(**c)).count(),
+            }
+        };
+        let mut pending: HashMap<Node, usize> = HashMap::new();
+        for &(s, cell) in &nodes {
+            // This counts each reference to a member, matching `referrers`.
+            let local = self.sheets[s].1.cell(cell.0, cell.1).map_or(0, |c| {
+                let refs = c.refs.iter().filter(|r| members[s].contains(r)).count();
+                let ranges: usize = c.ranges.iter().map(|r| covered(s, r)).sum();
+                refs + ranges
+            });
+            let other: usize = self
+                .sheet_refs_of((s, cell))
+                .iter()
+                .filter_map(|(name, r)| Some(covered(self.position(name)?, r)))
+                .sum();
+            pending.insert((s, cell), local + other);
+        }
+
+        let mut ready: Vec<Node> = nodes.iter().filter(|n| pending[n] == 0).copied().collect();
+        while let Some((s, cell)) = ready.pop() {
+            pending.remove(&(s, cell));
+            if self.sheets[s].1.cell(cell.0, cell.1).is_some() {
+                self.import(s, cell);
+                self.sheets[s].1.evaluate(cell);
+            }
+            for b in self.referrers(&index, (s, cell)) {
+                if let Some(n) = pending.get_mut(&b) {
+                    *n -= 1;
+                    if *n == 0 {
+                        ready.push(b);
+                    }
+                }
+            }
+        }
+
+        for (s, cell) in pending.keys() {
+            self.sheets[*s].1.cell_mut(cell.0, cell.1).value =
+                Value::Error(Error::CircularReference);
+        }
+    }
+
+    // Copies the definitions of the names `cell`'s formula uses, and the
+    // values of the cells it refers to on other sheets, into its own sheet,
+    // ready for evaluating it.
+    fn import(&mut self, s: usize, cell: CellRef) {
+        let names = match self.sheets[s].1.cell(cell.0, cell.1) {
+            Some(c) => c.names.clone(),
+            None => return,
+        };
+        for name in names {
+            match self.definition(&name).cloned() {
+                Some(definition) => self.sheets[s].1.names.insert(name, definition),
+                None => self.sheets[s].1.names.remove(&name),
+            };
+        }
+        for (name, range) in self.sheet_refs_of((s, cell)) {
+            let values: Option<Vec<(CellRef, Value)>> = self.position(&name).map(|t| {
+                self.sheets[t]
+                    .1
+                    .cells
+                    .iter()
+                    .filter(|(r, _)| range.contains(**r))
+                    .map(|(r, c)| (*r, c.value.clone()))
+                    .collect()
+            });
+            let imports = &mut self.sheets[s].1.imports;
+            match values {
+                Some(values) => {
+                    let cells = imports.entry(name).or_default();
+                    cells.retain(|r, _| !range.contains(*r));
+                    cells.extend(values);
+                }
+                None => {
+                    imports.remove(&name);
+                }
+            }
+        }
+    }
+}
+
+// Joins the cells two changes changed, for `Option::zip`.
+fn join((mut a, b): (Vec<Node>, Vec<Node>)) -> Vec<Node> {
+    a.extend(b);
+    a
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+// Names are spelled like functions: letters, digits and underscores, not
+// starting with a digit. Ones which formulas would read as something else,
+// like cell addresses, can't be used.
+fn check_defined_name(name: &str) -> Result<(), Error> {
+    let word = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match word && !is_address_like(name) && !["true", "false"].contains(&&*name.to_lowercase()) {
+        true => Ok(()),
+        false => Err(Error::DescriptiveError(format!(
+            "\"{}\" can't be used as a name",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TR = Result<(), Error>;
+
+    fn two_sheets() -> Result<Workbook, Error> {
+        let mut book = Workbook::new();
+        book.set(0, 0, "2".to_string());
+        book.add_sheet("Q1 Sales")?;
+        book.switch_sheet("q1 sales")?;
+        book.set(0, 0, "=Sheet1!A1*10".to_string());
+        book.set(1, 0, "=SUM(sheet1!A1:A3)+A1".to_string());
+        Ok(book)
+    }
+
+    #[test]
+    fn test_references_between_sheets() -> TR {
+        let mut book = two_sheets()?;
+        assert_eq!(book.sheet_name(), "Q1 Sales");
+        assert_eq!(book.sheet().show_cell(0, 0), "20");
+        assert_eq!(book.sheet().show_cell(1, 0), "22");
+
+        book.switch_sheet("Sheet1")?;
+        book.set(0, 0, "3".to_string());
+        book.set(0, 2, "1".to_string());
+        book.set(1, 0, "='Q1 Sales'!B1".to_string());
+        assert_eq!(book.sheet().show_cell(1, 0), "34");
+
+        book.set(0, 0, "=B1".to_string());
+        assert_eq!(book.sheet().show_cell(1, 0), "#CIRC!");
+        book.switch_sheet("Q1 Sales")?;
+        assert_eq!(book.sheet().show_cell(0, 0), "#CIRC!");
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_sheet() -> TR {
+        let mut book = Workbook::new();
+        book.set(0, 0, "=Later!A1+1".to_string());
+        assert_eq!(book.sheet().show_cell(0, 0), "#REF!");
+        book.add_sheet("later")?;
+        assert_eq!(book.sheet().show_cell(0, 0), "1");
+
+        let mut sheet = Spreadsheet::new();
+        sheet.set(0, 0, "=Sheet1!A1".to_string());
+        assert_eq!(sheet.show_cell(0, 0), "#REF!");
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo() -> TR {
+        let mut book = two_sheets()?;
+        let formula = |book: &Workbook| book.sheets()[1].1.raw_cell(0, 0);
+        let value = |book: &Workbook| book.sheets()[1].1.show_cell(1, 0);
+        book.switch_sheet("Sheet1")?;
+        book.set(0, 0, "5".to_string());
+        assert_eq!(value(&book), "55");
+        assert!(book.undo());
+        assert_eq!(value(&book), "22");
+
+        // Rewriting the other sheet's formulas is part of the same step.
+        book.insert_rows(0, 1);
+        assert_eq!(formula(&book), "=Sheet1!A2*10");
+        assert!(book.undo());
+        assert_eq!(formula(&book), "=Sheet1!A1*10");
+        assert_eq!(book.sheet().raw_cell(0, 0), "2");
+
+        book.rename_sheet("Sheet1", "Inputs")?;
+        book.define_name("Rate", "0.5")?;
+        book.delete_sheet("Q1 Sales")?;
+        assert!(book.undo());
+        assert_eq!(value(&book), "22");
+        assert!(book.undo());
+        assert!(book.names().is_empty());
+        assert!(book.undo());
+        assert_eq!(formula(&book), "=Sheet1!A1*10");
+        assert!(book.redo());
+        assert_eq!(formula(&book), "=Inputs!A1*10");
+        assert_eq!(value(&book), "22");
+
+        while book.undo() {}
+        assert_eq!(book.sheets().len(), 1);
+        assert_eq!(book.sheet().raw_cell(0, 0), "");
+        assert!(book.redo());
+        assert_eq!(book.sheet().show_cell(0, 0), "2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_recalculates_what_changed() -> TR {
+        let mut book = two_sheets()?;
+        book.define_name("Rate", "Sheet1!A1")?;
+        book.set(2, 0, "=Rate*3".to_string());
+        assert_eq!(book.sheet().show_cell(2, 0), "6");
+        book.delete_name("Rate")?;
+        assert_ne!(book.sheet().show_cell(2, 0), "6");
+        assert!(book.undo());
+        assert_eq!(book.sheet().show_cell(2, 0), "6");
+
+        book.switch_sheet("Sheet1")?;
+        book.set(0, 0, "5".to_string());
+        book.switch_sheet("Q1 Sales")?;
+        assert_eq!(book.sheet().show_cell(2, 0), "15");
+        assert!(book.undo());
+        assert_eq!(book.sheet().show_cell(2, 0), "6");
+        assert_eq!(book.sheet().show_cell(0, 0), "20");
+        assert!(book.redo());
+        assert_eq!(book.sheet().show_cell(1, 0), "55");
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_replace_all() -> TR {
+        let mut book = two_sheets()?;
+        book.define_name("Rate", "0.5")?;
+        let mut sheet = Spreadsheet::new();
+        sheet.fill(vec![(CellRef(0, 0), "7".to_string())]);
+        let other = Workbook::from_sheets(vec![("Loaded".to_string(), sheet)])?;
+
+        book.replace_all(other);
+        assert_eq!(book.sheet_name(), "Loaded");
+        assert!(book.names().is_empty());
+        assert!(book.undo());
+        assert_eq!(book.sheet_name(), "Q1 Sales");
+        assert_eq!(book.names(), [("Rate", "0.5".to_string())]);
+        assert_eq!(book.sheet().show_cell(1, 0), "22");
+        // The history from before the load is still there.
+        assert!(book.undo());
+        assert!(book.names().is_empty());
+        assert!(book.redo());
+        assert!(book.redo());
+        assert_eq!(book.sheet().show_cell(0, 0), "7");
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_is_bounded() -> TR {
+        let mut book = Workbook::new();
+        book.set(0, 0, "1".to_string());
+        book.set(1, 0, "=Sheet1!A1+A1".to_string());
+        // Each insertion is two steps on the sheet, one moving the cells and
+        // one rewriting the reference to the sheet by name.
+        let n = MAX_STEPS * 3;
+        for _ in 0..n {
+            book.insert_rows(0, 1);
+        }
+        assert_eq!(book.done.len(), MAX_STEPS);
+        assert!(book.sheet().steps() <= history::MAX_STEPS);
+
+        while book.undo() {}
+        let y = n - MAX_STEPS;
+        assert_eq!(book.sheet().raw_cell(0, y), "1");
+        let a1 = format!("A{}", y + 1);
+        assert_eq!(
+            book.sheet().raw_cell(1, y),
+            format!("=Sheet1!{}+{}", a1, a1)
+        );
+        assert_eq!(book.sheet().show_cell(1, y), "2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_and_delete() -> TR {
+        let mut book = two_sheets()?;
+        book.rename_sheet("Sheet1", "Inputs")?;
+        assert_eq!(book.sheet().raw_cell(0, 0), "=Inputs!A1*10");
+        assert_eq!(book.sheet().raw_cell(1, 0), "=SUM(Inputs!A1:A3)+A1");
+        assert_eq!(book.sheet().show_cell(1, 0), "22");
+
+        let message = |r: Result<(), Error>| r.unwrap_err().to_string();
+        assert_eq!(
+            message(book.rename_sheet("Inputs", "q1 SALES")),
+            "there is already a sheet named \"Q1 Sales\""
+        );
+        assert_eq!(
+            message(book.switch_sheet("Sheet1")),
+            "there is no sheet named \"Sheet1\""
+        );
+
+        book.delete_sheet("inputs")?;
+        assert_eq!(book.sheet().raw_cell(0, 0), "=#REF!*10");
+        assert_eq!(book.sheet().show_cell(1, 0), "#REF!");
+        assert_eq!(
+            message(book.delete_sheet("Q1 Sales")),
+            "can't delete the only sheet"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_names() -> TR {
+        let mut book = two_sheets()?;
+        book.set(1, 1, "100".to_string());
+        book.define_name("TaxRate", "0.5")?;
+        book.define_name("Subtotal", "B2")?;
+        book.set(2, 0, "=TaxRate * Subtotal".to_string());
+        assert_eq!(book.sheet().show_cell(2, 0), "50");
+        book.set(1, 1, "10".to_string());
+        assert_eq!(book.sheet().show_cell(2, 0), "5");
+
+        book.define_name("taxrate", "=1/4")?;
+        book.define_name("Inputs", "Sheet1!A1:A3")?;
+        book.set(3, 0, "=SUM(inputs)*TAXRATE".to_string());
+        assert_eq!(book.sheet().show_cell(2, 0), "2.5");
+        assert_eq!(book.sheet().show_cell(3, 0), "0.5");
+        assert_eq!(
+            book.names(),
+            [
+                ("taxrate", "1/4".to_string()),
+                ("Subtotal", "'Q1 Sales'!B2".to_string()),
+                ("Inputs", "Sheet1!A1:A3".to_string()),
+            ]
+        );
+
+        book.switch_sheet("Sheet1")?;
+        book.set(0, 2, "6".to_string());
+        book.insert_rows(0, 1);
+        assert_eq!(book.names()[2].1, "Sheet1!A2:A4");
+        book.switch_sheet("Q1 Sales")?;
+        assert_eq!(book.sheet().show_cell(3, 0), "2");
+
+        book.set(1, 1, "=Subtotal".to_string());
+        assert_eq!(book.sheet().show_cell(2, 0), "#CIRC!");
+        book.delete_name("Subtotal")?;
+        assert_eq!(book.sheet().show_cell(2, 0), "#NAME?");
+        assert_eq!(
+            book.sheet().cell_error(2, 0).map(|e| e.to_string()),
+            Some("unknown name \"Subtotal\"".to_string())
+        );
+
+        let message = |r: Result<(), Error>| r.unwrap_err().to_string();
+        assert_eq!(
+            message(book.define_name("Q1", "1")),
+            "\"Q1\" can't be used as a name"
+        );
+        assert_eq!(
+            message(book.define_name("Double", "A1*2")),
+            "a name can only stand for a cell, a range or a constant"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_structural_edits_follow_references() -> TR {
+        let mut book = two_sheets()?;
+        book.switch_sheet("Sheet1")?;
+        book.insert_rows(0, 2);
+        book.switch_sheet("Q1 Sales")?;
+        assert_eq!(book.sheet().raw_cell(0, 0), "=Sheet1!A3*10");
+        assert_eq!(book.sheet().raw_cell(1, 0), "=SUM(sheet1!A3:A5)+A1");
+        assert_eq!(book.sheet().show_cell(0, 0), "20");
+
+        book.switch_sheet("Sheet1")?;
+        book.delete_rows(2, 1);
+        book.switch_sheet("Q1 Sales")?;
+        assert_eq!(book.sheet().raw_cell(0, 0), "=#REF!*10");
+        assert_eq!(book.sheet().raw_cell(1, 0), "=SUM(sheet1!A3:A4)+A1");
+        assert_eq!(book.sheet().show_cell(1, 0), "#REF!");
+        Ok(())
+    }
+}
@@ -0,0 +1,938 @@
+// Reading and writing Excel workbooks (.xlsx), which are zip archives of XML
+// files. Only cell contents are kept: formatting, charts and the like are
+// dropped when reading and not written. The exception is number formats,
+// which Excel uses to keep how a number was typed: a percentage, a date, or
+// a number with trailing zeros like "2.50" is written with a format saying
+// so, and a number read with one of those formats gets contents to match.
+//
+// Excel writes formulas almost the same way we do. The differences are that
+// it spells IF, TRUE and FALSE in capitals, has a MOD function where we have
+// the "%" operator, and puts "%" after a value to divide it by 100. Newer
+// functions also get an "_xlfn." prefix in files. Formulas we can't parse,
+// like ones referring to other workbooks, are kept as they are, so that they
+// survive a round trip even though they evaluate to an error here.
+
+use super::input::{classify, date, date_text, text_contents};
+use super::parsing::{tokenize, Token, TokenKind};
+use super::{a1_name, parse_a1, Cell, CellRef, Error, Expr, Op, Spreadsheet, Value};
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+const MAIN_NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const REL_NS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PACKAGE_REL_NS: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n";
+
+// Returns every sheet in the workbook with its name, in workbook order.
+pub fn read_xlsx(bytes: &[u8]) -> Result<Vec<(String, Spreadsheet)>, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let targets = relationships(&read_part(&mut archive, "xl/_rels/workbook.xml.rels")?)?;
+    // Workbooks without any text needn't have shared strings.
+    let has_strings = archive.file_names().any(|n| n == "xl/sharedStrings.xml");
+    let strings = match has_strings {
+        true => shared_strings(&read_part(&mut archive, "xl/sharedStrings.xml")?)?,
+        false => vec![],
+    };
+    let has_styles = archive.file_names().any(|n| n == "xl/styles.xml");
+    let formats = match has_styles {
+        true => number_formats(&read_part(&mut archive, "xl/styles.xml")?)?,
+        false => vec![],
+    };
+
+    let mut sheets = vec![];
+    for (name, id) in sheet_list(&read_part(&mut archive, "xl/workbook.xml")?)? {
+        let target = targets.get(&id).ok_or_else(|| {
+            Error::DescriptiveError(format!("sheet \"{}\" has no worksheet part", name))
+        })?;
+        // Targets are relative to the workbook, unless they start with "/".
+        let path = match target.strip_prefix('/') {
+            Some(path) => path.to_string(),
+            None => format!("xl/{}", target),
+        };
+        let cells = worksheet(&read_part(&mut archive, &path)?, &strings, &formats)?;
+        let mut sheet = Spreadsheet::new();
+        sheet.fill(cells);
+        sheets.push((name, sheet));
+    }
+    Ok(sheets)
+}
+
+pub fn write_xlsx(sheets: &[(&str, &Spreadsheet)]) -> Result<Vec<u8>, Error> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut part = |path: &str, xml: String| -> Result<(), Error> {
+        zip.start_file(path, FileOptions::default())?;
+        zip.write_all(XML_HEADER.as_bytes())?;
+        zip.write_all(xml.as_bytes())?;
+        Ok(())
+    };
+
+    let mut types = String::new();
+    let mut entries = String::new();
+    let mut rels = String::new();
+    let mut formats = vec![];
+    for (i, (name, sheet)) in sheets.iter().enumerate() {
+        let n = i + 1;
+        types.push_str(&format!(
+            "<Override PartName=\"/xl/worksheets/sheet{}.xml\" \
+             ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>",
+            n
+        ));
+        entries.push_str(&format!(
+            "<sheet name=\"{}\" sheetId=\"{}\" r:id=\"rId{}\"/>",
+            escape(name),
+            n,
+            n
+        ));
+        rels.push_str(&format!(
+            "<Relationship Id=\"rId{}\" Type=\"{}/worksheet\" Target=\"worksheets/sheet{}.xml\"/>",
+            n, REL_NS, n
+        ));
+        part(
+            &format!("xl/worksheets/sheet{}.xml", n),
+            sheet_xml(sheet, &mut formats),
+        )?;
+    }
+    // The styles come after the sheets, which are rId1 and so on.
+    rels.push_str(&format!(
+        "<Relationship Id=\"rId{}\" Type=\"{}/styles\" Target=\"styles.xml\"/>",
+        sheets.len() + 1,
+        REL_NS
+    ));
+    part("xl/styles.xml", styles_xml(&formats))?;
+
+    part(
+        "[Content_Types].xml",
+        format!(
+            "<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+             <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+             <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+             <Override PartName=\"/xl/workbook.xml\" \
+             ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/>\
+             <Override PartName=\"/xl/styles.xml\" \
+             ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml\"/>\
+             {}</Types>",
+            types
+        ),
+    )?;
+    part(
+        "_rels/.rels",
+        format!(
+            "<Relationships xmlns=\"{}\"><Relationship Id=\"rId1\" \
+             Type=\"{}/officeDocument\" Target=\"xl/workbook.xml\"/></Relationships>",
+            PACKAGE_REL_NS, REL_NS
+        ),
+    )?;
+    part(
+        "xl/workbook.xml",
+        format!(
+            "<workbook xmlns=\"{}\" xmlns:r=\"{}\"><sheets>{}</sheets></workbook>",
+            MAIN_NS, REL_NS, entries
+        ),
+    )?;
+    part(
+        "xl/_rels/workbook.xml.rels",
+        format!(
+            "<Relationships xmlns=\"{}\">{}</Relationships>",
+            PACKAGE_REL_NS, rels
+        ),
+    )?;
+    Ok(zip.finish()?.into_inner())
+}
+
+// Cells with a number format use the style at its position in `formats`,
+// plus one, adding it if it's new.
+fn sheet_xml(sheet: &Spreadsheet, formats: &mut Vec<String>) -> String {
+    let mut cells: Vec<(&CellRef, &Cell)> = sheet
+        .cells
+        .iter()
+        .filter(|(_, c)| !c.contents.is_empty())
+        .collect();
+    cells.sort_by_key(|(r, _)| (r.1, r.0));
+
+    let mut rows = String::new();
+    let mut row = None;
+    for (r, cell) in cells {
+        if row != Some(r.1) {
+            if row.is_some() {
+                rows.push_str("</row>");
+            }
+            rows.push_str(&format!("<row r=\"{}\">", r.1 + 1));
+            row = Some(r.1);
+        }
+        let style =
+            number_format(&cell.contents).map(|f| match formats.iter().position(|g| *g == f) {
+                Some(i) => i + 1,
+                None => {
+                    formats.push(f);
+                    formats.len()
+                }
+            });
+        rows.push_str(&cell_xml(&a1_name(r.0, r.1), cell, style));
+    }
+    if row.is_some() {
+        rows.push_str("</row>");
+    }
+    format!(
+        "<worksheet xmlns=\"{}\"><sheetData>{}</sheetData></worksheet>",
+        MAIN_NS, rows
+    )
+}
+
+// Formulas are written along with the value they last evaluated to, which is
+// what other programs show until they recalculate.
+fn cell_xml(name: &str, cell: &Cell, style: Option<usize>) -> String {
+    let formula = match (cell.contents.strip_prefix('='), &cell.expr) {
+        (Some(_), Ok(e)) => Some(to_excel(e)),
+        (Some(text), Err(_)) => Some(text.to_string()),
+        (None, _) => None,
+    };
+    let value = match &cell.value {
+        Value::Int(x) => Some(("n", x.to_string())),
+        Value::Float(x) => Some(("n", format!("{:?}", x))),
+        Value::Bool(b) => Some(("b", (*b as u8).to_string())),
+        Value::String(s) if formula.is_some() => Some(("str", s.clone())),
+        Value::String(s) => {
+            return format!(
+                "<c r=\"{}\" t=\"inlineStr\"><is><t xml:space=\"preserve\">{}</t></is></c>",
+                name,
+                escape(s.as_str())
+            )
+        }
+        // Excel doesn't know about our own error codes, so those are left out.
+        Value::Error(e) => Error::from_code(e.code()).map(|_| ("e", e.code().to_string())),
+        Value::Blank | Value::Array(_) => None,
+    };
+
+    let mut xml = format!("<c r=\"{}\"", name);
+    if let Some(s) = style {
+        xml.push_str(&format!(" s=\"{}\"", s));
+    }
+    if let Some((t, _)) = value {
+        if t != "n" {
+            xml.push_str(&format!(" t=\"{}\"", t));
+        }
+    }
+    xml.push('>');
+    if let Some(f) = formula {
+        xml.push_str(&format!("<f>{}</f>", escape(f.as_str())));
+    }
+    if let Some((_, v)) = value {
+        xml.push_str(&format!("<v>{}</v>", escape(v.as_str())));
+    }
+    xml.push_str("</c>");
+    xml
+}
+
+// The stylesheet, with a cell style for each number format after the default
+// one. Excel expects a font, fill and border to be given even if unused.
+fn styles_xml(formats: &[String]) -> String {
+    let mut num_fmts = String::new();
+    let mut xfs =
+        "<xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\"/>".to_string();
+    for (i, f) in formats.iter().enumerate() {
+        num_fmts.push_str(&format!(
+            "<numFmt numFmtId=\"{}\" formatCode=\"{}\"/>",
+            FIRST_CUSTOM_FORMAT + i,
+            escape(f.as_str())
+        ));
+        xfs.push_str(&format!(
+            "<xf numFmtId=\"{}\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyNumberFormat=\"1\"/>",
+            FIRST_CUSTOM_FORMAT + i
+        ));
+    }
+    format!(
+        "<styleSheet xmlns=\"{}\"><numFmts count=\"{}\">{}</numFmts>\
+         <fonts count=\"1\"><font><sz val=\"11\"/><name val=\"Calibri\"/></font></fonts>\
+         <fills count=\"2\"><fill><patternFill patternType=\"none\"/></fill>\
+         <fill><patternFill patternType=\"gray125\"/></fill></fills>\
+         <borders count=\"1\"><border/></borders>\
+         <cellStyleXfs count=\"1\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\"/></cellStyleXfs>\
+         <cellXfs count=\"{}\">{}</cellXfs></styleSheet>",
+        MAIN_NS,
+        formats.len(),
+        num_fmts,
+        formats.len() + 1,
+        xfs
+    )
+}
+
+// Formats numbered below this are built into Excel.
+const FIRST_CUSTOM_FORMAT: usize = 164;
+
+// The number format which shows a number the way it was typed into a cell,
+// if the default one wouldn't.
+pub(super) fn number_format(contents: &str) -> Option<String> {
+    let s = contents.trim();
+    if date(s).is_some() {
+        return Some("yyyy-mm-dd".to_string());
+    }
+    match (classify(contents), s.strip_suffix('%')) {
+        (Expr::Float(_), Some(n)) => Some(format!("{}%", digits_format(n.trim_end())?)),
+        (Expr::Int(_) | Expr::Float(_), None) => {
+            let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+            let int = int.trim_start_matches(['+', '-']);
+            match frac.ends_with('0') || (int.len() > 1 && int.starts_with('0')) {
+                true => digits_format(s),
+                false => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// A format like "00.000" giving a number as many digits as `number` has on
+// each side of the point, or None if it has an exponent.
+fn digits_format(number: &str) -> Option<String> {
+    let number = number.trim_start_matches(['+', '-']);
+    if number.contains(['e', 'E']) {
+        return None;
+    }
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    let mut f = "0".repeat(int.len().max(1));
+    if !frac.is_empty() {
+        f.push('.');
+        f.push_str(&"0".repeat(frac.len()));
+    }
+    Some(f)
+}
+
+// The number format of each cell style, by the style's position, or "" for
+// the default format.
+fn number_formats(xml: &str) -> Result<Vec<String>, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut custom: HashMap<String, String> = HashMap::new();
+    let mut formats = vec![];
+    let mut in_cell_xfs = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"numFmt" => {
+                    let mut attrs = attributes(&e)?;
+                    if let (Some(id), Some(code)) =
+                        (attrs.remove("numFmtId"), attrs.remove("formatCode"))
+                    {
+                        custom.insert(id, code);
+                    }
+                }
+                b"cellXfs" => in_cell_xfs = true,
+                b"xf" if in_cell_xfs => {
+                    let id = attributes(&e)?.remove("numFmtId").unwrap_or_default();
+                    let code = match custom.get(&id) {
+                        Some(code) => code.clone(),
+                        None => built_in_format(id.parse().unwrap_or(0)).to_string(),
+                    };
+                    formats.push(code);
+                }
+                _ => (),
+            },
+            Event::End(e) if e.local_name().as_ref() == b"cellXfs" => in_cell_xfs = false,
+            Event::Eof => return Ok(formats),
+            _ => (),
+        }
+    }
+}
+
+// The number formats built into Excel which `formatted` can use.
+fn built_in_format(id: usize) -> &'static str {
+    match id {
+        1 => "0",
+        2 => "0.00",
+        9 => "0%",
+        10 => "0.00%",
+        14..=17 => "yyyy-mm-dd",
+        _ => "",
+    }
+}
+
+// The contents for a number cell showing `value` in a number format, if the
+// format is one which we can type: a percentage, a date or a number with a
+// set number of digits. Other formats, like currencies, are dropped.
+fn formatted(value: &str, format: &str) -> Option<String> {
+    let x: f64 = value.parse().ok()?;
+    // Only the first section of a format is for positive numbers, and it's
+    // the one with the shape we're after.
+    let format = format.split(';').next()?;
+    let digits = |f: &str| {
+        let (int, frac) = f.split_once('.').unwrap_or((f, ""));
+        let count = |s: &str| s.chars().filter(|c| *c == '0').count();
+        (count(int), count(frac))
+    };
+    let fixed = |x: f64, (int, frac): (usize, usize)| {
+        let width = int + if frac > 0 { frac + 1 } else { 0 } + (x < 0.0) as usize;
+        format!("{:0width$.frac$}", x, width = width, frac = frac)
+    };
+
+    let codes = format_codes(format).to_ascii_lowercase();
+    if let Some(f) = format.strip_suffix('%') {
+        Some(format!("{}%", fixed(x * 100.0, digits(f))))
+    } else if codes.contains('y') || codes.contains('d') {
+        match x.fract() {
+            0.0 => date_text(x as i64),
+            // Times aren't something we can type.
+            _ => None,
+        }
+    } else if !format.is_empty() && format.chars().all(|c| matches!(c, '0' | '.')) {
+        Some(fixed(x, digits(format)))
+    } else {
+        None
+    }
+}
+
+// A number format without the parts which are shown as they are rather
+// than standing for part of the number: quoted text, characters escaped
+// with "\" (or with "_" and "*", which pad with them), and sections in
+// brackets like "[Red]".
+fn format_codes(format: &str) -> String {
+    let mut codes = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => while chars.next().is_some_and(|c| c != '"') {},
+            '[' => while chars.next().is_some_and(|c| c != ']') {},
+            '\\' | '_' | '*' => {
+                chars.next();
+            }
+            c => codes.push(c),
+        }
+    }
+    codes
+}
+
+pub(super) fn read_part(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    path: &str,
+) -> Result<String, Error> {
+    let mut text = String::new();
+    archive
+        .by_name(path)
+        .map_err(|e| Error::DescriptiveError(format!("{}: {}", path, e)))?
+        .read_to_string(&mut text)?;
+    Ok(text)
+}
+
+// An element's attributes, keyed by their names without any namespace prefix.
+fn attributes(e: &BytesStart) -> Result<HashMap<String, String>, Error> {
+    let mut attrs = HashMap::new();
+    for a in e.attributes() {
+        let a = a.map_err(quick_xml::Error::from)?;
+        let key = String::from_utf8_lossy(a.key.local_name().as_ref()).into_owned();
+        attrs.insert(key, a.unescape_value()?.into_owned());
+    }
+    Ok(attrs)
+}
+
+// Maps relationship ids to the parts they point at.
+fn relationships(xml: &str) -> Result<HashMap<String, String>, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut targets = HashMap::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                let mut attrs = attributes(&e)?;
+                if let (Some(id), Some(target)) = (attrs.remove("Id"), attrs.remove("Target")) {
+                    targets.insert(id, target);
+                }
+            }
+            Event::Eof => return Ok(targets),
+            _ => (),
+        }
+    }
+}
+
+// The names of the sheets and their relationship ids.
+fn sheet_list(xml: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut sheets = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"sheet" => {
+                let mut attrs = attributes(&e)?;
+                if let (Some(name), Some(id)) = (attrs.remove("name"), attrs.remove("id")) {
+                    sheets.push((name, id));
+                }
+            }
+            Event::Eof => return Ok(sheets),
+            _ => (),
+        }
+    }
+}
+
+// A shared string may be split into runs with different formatting, and may
+// have phonetic guides, which aren't part of its text.
+fn shared_strings(xml: &str) -> Result<Vec<String>, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut strings = vec![];
+    let mut in_t = false;
+    let mut in_phonetic = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"si" => strings.push(String::new()),
+                b"t" => in_t = !in_phonetic,
+                b"rPh" => in_phonetic = true,
+                _ => (),
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                b"t" => in_t = false,
+                b"rPh" => in_phonetic = false,
+                _ => (),
+            },
+            Event::Empty(e) if e.local_name().as_ref() == b"si" => strings.push(String::new()),
+            Event::Text(t) if in_t => {
+                if let Some(s) = strings.last_mut() {
+                    s.push_str(&t.unescape()?);
+                }
+            }
+            Event::Eof => return Ok(strings),
+            _ => (),
+        }
+    }
+}
+
+// What we've read of a `<c>` element so far.
+#[derive(Default)]
+struct CellXml {
+    at: Option<CellRef>,
+    kind: String,
+    value: String,
+    formula: Option<String>,
+    // For shared formulas: the group the formula belongs to.
+    shared: Option<String>,
+    // The position of the cell's style.
+    style: usize,
+}
+
+fn worksheet(
+    xml: &str,
+    strings: &[String],
+    formats: &[String],
+) -> Result<Vec<(CellRef, String)>, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut cells = vec![];
+    // The first cell of each shared formula group, with its formula.
+    let mut shared: HashMap<String, (CellRef, String)> = HashMap::new();
+    let mut c = CellXml::default();
+    // Rows and cells may leave out their addresses, and then follow on from
+    // the one before.
+    let mut row = 0;
+    let mut next_col = 0;
+    // The element whose text we're collecting.
+    let mut text_of: Option<Vec<u8>> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"row" => {
+                row = match attributes(&e)?.get("r") {
+                    Some(r) => r.parse::<usize>()?.saturating_sub(1),
+                    None if cells.is_empty() => 0,
+                    None => row + 1,
+                };
+                next_col = 0;
+            }
+            Event::Start(e) if e.local_name().as_ref() == b"c" => {
+                c = start_cell(&e, row, &mut next_col)?;
+            }
+            // An empty cell, perhaps with a style, still takes up a column.
+            Event::Empty(e) if e.local_name().as_ref() == b"c" => {
+                start_cell(&e, row, &mut next_col)?;
+            }
+            Event::Start(e) if e.local_name().as_ref() == b"f" => {
+                let attrs = attributes(&e)?;
+                if attrs.get("t").map(String::as_str) == Some("shared") {
+                    c.shared = attrs.get("si").cloned();
+                }
+                c.formula = Some(String::new());
+                text_of = Some(b"f".to_vec());
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"f" => {
+                c.shared = attributes(&e)?.get("si").cloned();
+                c.formula = Some(String::new());
+            }
+            Event::Start(e) if matches!(e.local_name().as_ref(), b"v" | b"t") => {
+                text_of = Some(e.local_name().as_ref().to_vec());
+            }
+            Event::Text(t) => match text_of.as_deref() {
+                Some(b"f") => c
+                    .formula
+                    .get_or_insert_with(String::new)
+                    .push_str(&t.unescape()?),
+                Some(_) => c.value.push_str(&t.unescape()?),
+                None => (),
+            },
+            Event::End(e) if e.local_name().as_ref() == b"c" => {
+                let c = std::mem::take(&mut c);
+                let at = c.at.ok_or_else(|| {
+                    Error::DescriptiveError("a cell ends without having started".to_string())
+                })?;
+                if let Some(contents) = cell_contents(&c, at, strings, formats, &mut shared) {
+                    cells.push((at, contents));
+                }
+            }
+            Event::End(_) => text_of = None,
+            Event::Eof => return Ok(cells),
+            _ => (),
+        }
+    }
+}
+
+// Reads the address and attributes of a `<c>` element, which is at
+// `next_col` if it doesn't give an address, and moves `next_col` past it.
+fn start_cell(e: &BytesStart, row: usize, next_col: &mut usize) -> Result<CellXml, Error> {
+    let attrs = attributes(e)?;
+    let at = match attrs.get("r") {
+        Some(r) => parse_a1(r)
+            .ok_or_else(|| Error::DescriptiveError(format!("\"{}\" is not a cell address", r)))?,
+        None => (*next_col, row),
+    };
+    *next_col = at.0 + 1;
+    Ok(CellXml {
+        at: Some(CellRef(at.0, at.1)),
+        kind: attrs.get("t").cloned().unwrap_or_default(),
+        style: attrs.get("s").and_then(|s| s.parse().ok()).unwrap_or(0),
+        ..CellXml::default()
+    })
+}
+
+fn cell_contents(
+    c: &CellXml,
+    at: CellRef,
+    strings: &[String],
+    formats: &[String],
+    shared: &mut HashMap<String, (CellRef, String)>,
+) -> Option<String> {
+    match (&c.formula, &c.shared) {
+        // Part of a group of cells sharing the formula of its first cell,
+        // moved along as though it had been copied there.
+        (Some(f), Some(si)) if f.is_empty() && shared.contains_key(si) => {
+            let (from, contents) = &shared[si];
+            let (dx, dy) = (
+                at.0 as isize - from.0 as isize,
+                at.1 as isize - from.1 as isize,
+            );
+            let moved = Expr::parse(&contents[1..]).and_then(|e| e.shift(dx, dy));
+            return Some(match moved {
+                Ok(e) => format!("={}", e),
+                // We can't move what we can't parse, so it's copied as it is.
+                Err(_) => contents.clone(),
+            });
+        }
+        // Without the group's first cell, all we have is the value the
+        // formula last gave.
+        (Some(f), Some(_)) if f.is_empty() => (),
+        (Some(f), si) => {
+            let contents = from_excel(f);
+            if let Some(si) = si {
+                shared.insert(si.clone(), (at, contents.clone()));
+            }
+            return Some(contents);
+        }
+        (None, _) => (),
+    }
+
+    let text = match c.kind.as_str() {
+        "s" => strings.get(c.value.parse::<usize>().ok()?)?.clone(),
+        "b" => match c.value.as_str() {
+            "1" => "TRUE".to_string(),
+            _ => "FALSE".to_string(),
+        },
+        // An error value on its own is kept as a formula giving that error.
+        "e" => return Some(format!("={}", c.value)),
+        _ => c.value.clone(),
+    };
+    match c.kind.as_str() {
+        "s" | "inlineStr" | "str" => Some(text_contents(&text)),
+        _ if text.is_empty() => None,
+        "" | "n" => Some(
+            formats
+                .get(c.style)
+                .and_then(|f| formatted(&text, f))
+                .unwrap_or(text),
+        ),
+        _ => Some(text),
+    }
+}
+
+// Turns a formula as Excel stores it into cell contents, with the "=".
+// Formulas we can't parse keep their "_xlfn." prefixes, so that Excel still
+// knows the functions when they're written back.
+pub(super) fn from_excel(formula: &str) -> String {
+    match parse_excel(&formula.replace("_xlfn.", "")) {
+        Some(e) => format!("={}", e),
+        None => format!("={}", formula),
+    }
+}
+
+pub(super) fn parse_excel(formula: &str) -> Option<Expr> {
+    let tokens = tokenize(formula).ok()?;
+    let e = Expr::parse(&respell_from_excel(&tokens)).ok()?;
+    Some(rewrite(e, &mod_to_operator))
+}
+
+// Writes a formula as Excel expects it, without the "=".
+pub(super) fn to_excel(expr: &Expr) -> String {
+    let text = rewrite(expr.clone(), &mod_to_function).to_string();
+    match tokenize(&text) {
+        Ok(tokens) => tokens
+            .iter()
+            .map(|t| match t.kind {
+                TokenKind::Ident if ["if", "true", "false"].contains(&t.text.as_str()) => {
+                    t.text.to_ascii_uppercase()
+                }
+                _ => t.text.clone(),
+            })
+            .collect(),
+        Err(_) => text,
+    }
+}
+
+fn respell_from_excel(tokens: &[Token]) -> String {
+    let mut out: Vec<String> = vec![];
+    // Where the last complete operand starts in `out`, for a "%" to apply
+    // to, and where each open bracket is.
+    let mut operand = 0;
+    let mut brackets = vec![];
+    for t in tokens {
+        match (t.kind, t.text.as_str()) {
+            (TokenKind::Operator, "%") => {
+                out.insert(operand, "(".to_string());
+                out.push("/100)".to_string());
+                continue;
+            }
+            (TokenKind::Punct, "(") => brackets.push(out.len()),
+            (TokenKind::Punct, ")") => {
+                operand = brackets.pop().unwrap_or(0);
+                // Include the name of a function being called.
+                if operand > 0 && is_name(&out[operand - 1]) {
+                    operand -= 1;
+                }
+            }
+            (TokenKind::Operator, _) | (TokenKind::Punct, _) => (),
+            // A cell on another sheet starts at the sheet's name.
+            _ if out.last().is_some_and(|t| t == "!") => (),
+            _ => operand = out.len(),
+        }
+        out.push(match t.kind {
+            TokenKind::Ident
+                if ["IF", "TRUE", "FALSE"].contains(&&*t.text.to_ascii_uppercase()) =>
+            {
+                t.text.to_ascii_lowercase()
+            }
+            _ => t.text.clone(),
+        });
+    }
+    out.join(" ")
+}
+
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+fn mod_to_operator(e: Expr) -> Expr {
+    match e {
+        Expr::FnCall(name, mut args) if name.eq_ignore_ascii_case("MOD") && args.len() == 2 => {
+            let y = args.pop().unwrap();
+            let x = args.pop().unwrap();
+            Expr::BinOp(Op::Mod, Box::new(x), Box::new(y))
+        }
+        e => e,
+    }
+}
+
+fn mod_to_function(e: Expr) -> Expr {
+    match e {
+        Expr::BinOp(Op::Mod, x, y) => Expr::FnCall("MOD".to_string(), vec![*x, *y]),
+        e => e,
+    }
+}
+
+// Applies `f` to every part of an expression, innermost first.
+fn rewrite(e: Expr, f: &impl Fn(Expr) -> Expr) -> Expr {
+    let sub = |e: Box<Expr>| Box::new(rewrite(*e, f));
+    f(match e {
+        Expr::Neg(x) => Expr::Neg(sub(x)),
+        Expr::BinOp(op, x, y) => Expr::BinOp(op, sub(x), sub(y)),
+        Expr::If(b, x, y) => Expr::If(sub(b), sub(x), sub(y)),
+        Expr::FnCall(name, args) => {
+            Expr::FnCall(name, args.into_iter().map(|a| rewrite(a, f)).collect())
+        }
+        e => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TR = Result<(), Error>;
+
+    #[test]
+    fn test_from_excel() {
+        assert_eq!(from_excel("SUM(A1:B2)*2"), "=SUM(A1:B2)*2");
+        assert_eq!(from_excel("IF(TRUE,1,$B$3)"), "=if(true,1,$B$3)");
+        assert_eq!(from_excel("MOD(A1,3)+1"), "=A1%3+1");
+        assert_eq!(from_excel("50%*A1"), "=50/100*A1");
+        assert_eq!(from_excel("2^SUM(1,2)%"), "=2^(SUM(1,2)/100)");
+        assert_eq!(
+            from_excel("_xlfn.IFERROR(1/0,\"x\")"),
+            "=IFERROR(1/0,\"x\")"
+        );
+        assert_eq!(from_excel("Sheet2!A1+1"), "=Sheet2!A1+1");
+        assert_eq!(from_excel("2^'Q1 Sales'!B2%"), "=2^('Q1 Sales'!B2/100)");
+        assert_eq!(from_excel("[1]Sheet2!A1"), "=[1]Sheet2!A1");
+        assert_eq!(
+            from_excel("_xlfn.XLOOKUP(1,[1]Sheet2!A:A,B1:B9)"),
+            "=_xlfn.XLOOKUP(1,[1]Sheet2!A:A,B1:B9)"
+        );
+    }
+
+    #[test]
+    fn test_to_excel() -> TR {
+        let excel = |s: &str| Expr::parse(s).map(|e| to_excel(&e));
+        assert_eq!(excel("if(A1>0,true,\"if\")")?, "IF(A1>0,TRUE,\"if\")");
+        assert_eq!(excel("(A1+1)%2")?, "MOD(A1+1,2)");
+        assert_eq!(excel("Ref(1,2)*1.5")?, "$B$3*1.5");
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> TR {
+        let mut first = Spreadsheet::new();
+        first.set(0, 0, "Total".to_string());
+        first.set(1, 0, "=B2%3".to_string());
+        first.set(1, 1, "7".to_string());
+        first.set(2, 1, "'007".to_string());
+        first.set(0, 2, "=if(B2>1,\"big\",\"small\")".to_string());
+        first.set(1, 2, "=1/0".to_string());
+        first.set(2, 2, "=Other!A1".to_string());
+        let mut second = Spreadsheet::new();
+        second.set(0, 0, "true".to_string());
+        second.set(1, 0, "2.5".to_string());
+
+        let bytes = write_xlsx(&[("First", &first), ("Second & more", &second)])?;
+        let sheets = read_xlsx(&bytes)?;
+        let names: Vec<&str> = sheets.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["First", "Second & more"]);
+
+        let loaded = &sheets[0].1;
+        for (x, y) in [(0, 0), (1, 0), (1, 1), (2, 1), (0, 2), (1, 2), (2, 2)] {
+            assert_eq!(loaded.raw_cell(x, y), first.raw_cell(x, y));
+        }
+        assert_eq!(loaded.show_cell(1, 0), "1");
+        assert_eq!(loaded.show_cell(2, 1), "007");
+        let loaded = &sheets[1].1;
+        assert_eq!(loaded.raw_cell(0, 0), "TRUE");
+        assert_eq!(loaded.raw_cell(1, 0), "2.5");
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_formats_round_trip() -> TR {
+        let typed = ["10%", "12.5%", "2024-03-15", "2.50", "007", "-0.10"];
+        let mut sheet = Spreadsheet::new();
+        for (y, contents) in typed.iter().enumerate() {
+            sheet.set(0, y, contents.to_string());
+            sheet.set(1, y, contents.to_string());
+        }
+        sheet.set(2, 0, "=A1*2".to_string());
+
+        let loaded = &read_xlsx(&write_xlsx(&[("Sheet1", &sheet)])?)?[0].1;
+        for (y, contents) in typed.iter().enumerate() {
+            assert_eq!(loaded.raw_cell(0, y), *contents);
+            assert_eq!(loaded.raw_cell(1, y), *contents);
+        }
+        assert_eq!(loaded.show_cell(2, 0), "0.2");
+
+        assert_eq!(number_format("2.5"), None);
+        assert_eq!(number_format("1.50e3"), None);
+        // Formats we can't type keep the plain value.
+        assert_eq!(formatted("0.5", "0.0%").as_deref(), Some("50.0%"));
+        assert_eq!(formatted("45366.25", "yyyy-mm-dd"), None);
+        assert_eq!(formatted("3", "\"$\"#,##0.00"), None);
+        // Quoted text, escapes and colours aren't dates.
+        assert_eq!(formatted("42", "0 \"days\""), None);
+        assert_eq!(formatted("42", "#,##0 \"units sold\""), None);
+        assert_eq!(formatted("42", "[Red]0.00"), None);
+        assert_eq!(formatted("42", "0\\d"), None);
+        assert_eq!(
+            formatted("45366", "[$-409]d mmm yyyy").as_deref(),
+            Some("2024-03-15")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cells_without_addresses() -> TR {
+        let xml = format!(
+            "<worksheet xmlns=\"{}\"><sheetData><row r=\"1\">\
+             <c s=\"1\"/><c><v>5</v></c></row></sheetData></worksheet>",
+            MAIN_NS
+        );
+        let cells = worksheet(&xml, &[], &[])?;
+        assert_eq!(cells, [(CellRef(1, 0), "5".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_formula_without_first_cell() -> TR {
+        let xml = format!(
+            "<worksheet xmlns=\"{}\"><sheetData><row r=\"1\">\
+             <c r=\"B1\"><f t=\"shared\" si=\"0\"/><v>7</v></c>\
+             <c r=\"C1\" t=\"str\"><f t=\"shared\" si=\"0\"/><v>x</v></c>\
+             </row></sheetData></worksheet>",
+            MAIN_NS
+        );
+        let cells = worksheet(&xml, &[], &[])?;
+        assert_eq!(
+            cells,
+            [
+                (CellRef(1, 0), "7".to_string()),
+                (CellRef(2, 0), "x".to_string())
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_worksheet() {
+        let xml = format!(
+            "<worksheet xmlns=\"{}\"><sheetData><row r=\"1\">\
+             <c r=\"A1\"><c r=\"B1\"><v>1</v></c></c></row></sheetData></worksheet>",
+            MAIN_NS
+        );
+        assert!(worksheet(&xml, &[], &[]).is_err());
+    }
+
+    // The fixture was put together by hand following what Excel writes,
+    // rather than saved by Excel itself. It has shared strings, a shared
+    // formula, percentages, a styled empty cell and a function with a prefix.
+    #[test]
+    fn test_read_fixture() -> TR {
+        let sheets = read_xlsx(include_bytes!("../../tests/fixtures/budget.xlsx"))?;
+        assert_eq!(sheets.len(), 2);
+        let (name, budget) = &sheets[0];
+        assert_eq!(name, "Budget");
+        assert_eq!(budget.raw_cell(0, 0), "Item");
+        assert_eq!(budget.raw_cell(0, 1), "Rent");
+        assert_eq!(budget.raw_cell(1, 1), "1200");
+        assert_eq!(budget.raw_cell(2, 1), "=B2*(1+10/100)");
+        assert_eq!(budget.raw_cell(2, 3), "=B4*(1+10/100)");
+        assert_eq!(budget.show_cell(2, 3), "55");
+        assert_eq!(budget.raw_cell(1, 4), "=SUM(B2:B4)");
+        assert_eq!(budget.show_cell(1, 4), "1400");
+        assert_eq!(budget.raw_cell(2, 4), "=IFERROR(B5/0,\"none\")");
+        assert_eq!(budget.show_cell(2, 4), "\"none\"");
+        assert_eq!(budget.raw_cell(0, 5), "'0042");
+
+        let (name, notes) = &sheets[1];
+        assert_eq!(name, "Notes");
+        assert_eq!(notes.raw_cell(0, 0), "Rich text");
+        assert_eq!(notes.raw_cell(1, 0), "TRUE");
+        assert_eq!(notes.raw_cell(0, 1), "=Budget!B5");
+        Ok(())
+    }
+}
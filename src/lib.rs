@@ -0,0 +1,3 @@
+// The spreadsheet engine, for use by the REPL in main.rs and by benchmarks.
+
+pub mod engine;
@@ -1,19 +1,19 @@
-mod engine;
-
+use spreadsheet::engine;
+use std::fs;
 use std::io::{self, Write};
 
 fn main() {
-    let mut sheet = engine::Spreadsheet::new();
+    let mut book = engine::Workbook::new();
     let mut line = String::new();
     loop {
         if !line.is_empty() {
-            match run_line(&line, &mut sheet) {
+            match run_line(&line, &mut book) {
                 Ok(_) => (),
                 Err(err) => println!("{}", err),
             }
         }
 
-        show_spreadsheet(&sheet);
+        show_workbook(&book);
 
         print!("> ");
         io::stdout().flush().unwrap();
@@ -32,49 +32,272 @@ fn main() {
     }
 }
 
-fn run_line(line: &String, sheet: &mut engine::Spreadsheet) -> Result<(), engine::Error> {
+fn run_line(line: &str, book: &mut engine::Workbook) -> Result<(), engine::Error> {
     let (cmd, rest) = line.split_once(' ').unwrap_or((line, ""));
     match cmd {
         "help" => help(),
-        "set" => match rest.splitn(3, ' ').collect::<Vec<_>>().as_slice() {
-            [col, row, val] => {
-                let col = col.parse::<usize>()?;
-                let row = row.parse::<usize>()?;
-                sheet.set(col, row, val.to_string());
+        "set" => match parse_address(rest)? {
+            Some((col, row, val)) => book.set(col, row, val.to_string()),
+            None => println!(
+                "expected \"set cell val\" or \"set col row val\", got: \"{}\"",
+                rest
+            ),
+        },
+        "info" => match parse_address(rest)? {
+            Some((col, row, "")) => {
+                let contents = book.sheet().raw_cell(col, row);
+                let name = engine::a1_name(col, row);
+                println!("cell {} has contents: {}", name, contents);
+                if let Some(err) = book.sheet().cell_error(col, row) {
+                    println!("error: {}", err);
+                }
+            }
+            _ => println!(
+                "expected \"info cell\" or \"info col row\", got: \"{}\"",
+                rest
+            ),
+        },
+        "clear" => match rest.split_once(':') {
+            Some((from, to)) => match (engine::parse_a1(from), engine::parse_a1(to)) {
+                (Some(from), Some(to)) => book.clear_range(from, to),
+                _ => println!("expected a range like A1:B3, got: \"{}\"", rest),
+            },
+            None => match parse_address(rest)? {
+                Some((col, row, "")) => book.clear(col, row),
+                _ => println!(
+                    "expected \"clear cell\", \"clear col row\" or \"clear range\", got: \"{}\"",
+                    rest
+                ),
+            },
+        },
+        "load" => match rest {
+            "" => println!("expected \"load path\""),
+            path if path.ends_with(".xlsx") || path.ends_with(".ods") => {
+                let bytes = fs::read(path)?;
+                let sheets = match path.ends_with(".xlsx") {
+                    true => engine::read_xlsx(&bytes)?,
+                    false => engine::read_ods(&bytes)?,
+                };
+                book.replace_all(engine::Workbook::from_sheets(sheets)?);
+            }
+            path if is_native(path) => {
+                book.replace_all(engine::Workbook::from_native(&fs::read_to_string(path)?)?)
+            }
+            path => {
+                let text = fs::read_to_string(path)?;
+                book.replace_sheet(match json_lines(path) {
+                    Some(lines) => engine::Spreadsheet::from_json(&text, lines)?,
+                    None => engine::Spreadsheet::from_csv(&text, delimiter_for(path))?,
+                });
+            }
+        },
+        "save" => match rest.split_once(' ').unwrap_or((rest, "")) {
+            ("", _) => println!("expected \"save path\" or \"save path values\""),
+            (path, "") if is_native(path) => fs::write(path, book.to_native())?,
+            (path, "") if path.ends_with(".xlsx") => {
+                fs::write(path, engine::write_xlsx(&book.sheets())?)?
+            }
+            (path, "") if path.ends_with(".ods") => {
+                fs::write(path, engine::write_ods(&book.sheets())?)?
             }
-            _ => println!("expected \"set col row val\", got: \"{}\"", rest),
+            // These keep formulas and values both, so there's no mode to pick.
+            (path, mode) if is_native(path) || path.ends_with(".xlsx") || path.ends_with(".ods") => {
+                println!(
+                    "expected nothing after a .sheet, .xlsx or .ods path, got: \"{}\"",
+                    mode
+                )
+            }
+            (path, mode @ ("" | "records")) if json_lines(path).is_some() => {
+                let options = engine::JsonOptions {
+                    shape: match mode {
+                        "records" => engine::JsonShape::Records,
+                        _ => engine::JsonShape::Rows,
+                    },
+                    lines: json_lines(path) == Some(true),
+                };
+                fs::write(path, book.sheet().to_json(&options))?;
+            }
+            (path, mode @ ("" | "values")) if json_lines(path).is_none() => {
+                let options = engine::CsvOptions {
+                    delimiter: delimiter_for(path),
+                    values: mode == "values",
+                };
+                fs::write(path, book.sheet().to_csv(&options))?;
+            }
+            (_, mode) => println!(
+                "expected \"values\", or \"records\" for JSON, after the path, got: \"{}\"",
+                mode
+            ),
+        },
+        "copy" => match rest.split_once(' ') {
+            Some((src, dst)) => match (engine::parse_a1(src), engine::parse_a1(dst)) {
+                (Some(src), Some(dst)) => book.copy_cell(src, dst)?,
+                _ => println!("expected two cell addresses, got: \"{}\"", rest),
+            },
+            None => println!("expected \"copy src dst\", got: \"{}\"", rest),
+        },
+        "sheet" => match rest.split_once(' ').unwrap_or((rest, "")) {
+            ("add", name) => book.add_sheet(name)?,
+            ("rename", name) => {
+                let current = book.sheet_name().to_string();
+                book.rename_sheet(&current, name)?
+            }
+            ("switch", name) => book.switch_sheet(name)?,
+            ("delete", name) => book.delete_sheet(name)?,
+            _ => println!(
+                "expected \"sheet add name\", \"sheet rename name\", \"sheet switch name\" \
+                 or \"sheet delete name\", got: \"{}\"",
+                rest
+            ),
         },
-        "info" => match rest.splitn(2, ' ').collect::<Vec<_>>().as_slice() {
-            [col, row] => {
-                let col = col.parse::<usize>()?;
-                let row = row.parse::<usize>()?;
-                let contents = sheet.raw_cell(col, row);
-                println!("cell {}:{} has contents: {}", col, row, contents);
-            }
-            _ => println!("expected \"info col row\", got: \"{}\"", rest),
+        "name" => match rest.split_once(' ').unwrap_or((rest, "")) {
+            ("", _) => {
+                for (name, definition) in book.names() {
+                    println!("{}\t{}", name, definition);
+                }
+            }
+            ("delete", name) => book.delete_name(name)?,
+            ("set", rest) => match rest.split_once(' ') {
+                Some((name, definition)) => book.define_name(name, definition)?,
+                None => println!("expected \"name set name definition\", got: \"{}\"", rest),
+            },
+            _ => println!(
+                "expected \"name\", \"name set name definition\" or \"name delete name\", got: \"{}\"",
+                rest
+            ),
         },
+        "undo" => {
+            if !book.undo() {
+                println!("nothing to undo");
+            }
+        }
+        "redo" => {
+            if !book.redo() {
+                println!("nothing to redo");
+            }
+        }
         _ => println!("command not recognized: \"{}\"", cmd),
     }
     Ok(())
 }
 
+// Files ending in ".sheet" are in the native format, which keeps everything.
+fn is_native(path: &str) -> bool {
+    path.ends_with(".sheet")
+}
+
+// Files ending in ".json" hold one JSON array, and ones ending in ".ndjson"
+// or ".jsonl" hold a JSON value on each line.
+fn json_lines(path: &str) -> Option<bool> {
+    match path.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("json") => Some(false),
+        Some("ndjson" | "jsonl") => Some(true),
+        _ => None,
+    }
+}
+
+// Files ending in ".tsv" are tab-separated; anything else is comma-separated.
+fn delimiter_for(path: &str) -> char {
+    if path.ends_with(".tsv") {
+        '\t'
+    } else {
+        ','
+    }
+}
+
+// Parses a cell address off the front of `s`, given either as "B3" or as
+// "col row", and returns it along with the rest of the line.
+fn parse_address(s: &str) -> Result<Option<(usize, usize, &str)>, engine::Error> {
+    let (first, rest) = s.split_once(' ').unwrap_or((s, ""));
+    if let Some((col, row)) = engine::parse_a1(first) {
+        return Ok(Some((col, row, rest)));
+    }
+    match rest.split_once(' ').unwrap_or((rest, "")) {
+        ("", _) => Ok(None),
+        (row, rest) => {
+            let col = first.parse::<usize>()?;
+            let row = row.parse::<usize>()?;
+            Ok(Some((col, row, rest)))
+        }
+    }
+}
+
 fn help() {
     println!("commands:");
     println!("\thelp\tprints this help screen");
+    println!("\tset cell val\tsets the contents of cell (e.g. B3) to val");
     println!("\tset col row val\tsets the contents of cell col:row to val");
+    println!("\tinfo cell\tprints the raw contents of cell");
+    println!("\tcopy src dst\tcopies cell src to dst, moving relative references");
+    println!("\tclear cell\tempties cell, or every cell in a range like A1:B3");
+    println!("\tload path\tloads a file, picking the format from its ending:");
+    println!("\t\t.sheet, .xlsx or .ods replaces the whole workbook");
+    println!("\t\t.json, .ndjson or .jsonl replaces the current sheet with the values");
+    println!("\t\t.tsv, or CSV for any other ending, replaces the current sheet");
+    println!("\tsave path\tsaves to a file, picking the format the same way:");
+    println!("\t\t.sheet, .xlsx or .ods saves the whole workbook");
+    println!("\t\t.json, .ndjson or .jsonl saves the current sheet's values as rows");
+    println!("\t\t.tsv, or CSV for any other ending, saves the current sheet's contents");
+    println!("\tsave path values\tsaves a CSV or TSV file with the computed values");
+    println!("\tsave path records\tsaves a JSON file as records keyed by the first row");
+    println!("\tsheet add name\tadds an empty sheet; formulas refer to its cells like name!A1");
+    println!("\tsheet rename name\trenames the current sheet");
+    println!("\tsheet switch name\tmakes the named sheet the current one");
+    println!("\tsheet delete name\tdeletes the named sheet");
+    println!("\tname\tlists the names formulas can use");
+    println!("\tname set name def\tlets formulas use name for a cell, range or constant,");
+    println!("\t\tlike \"name set TaxRate 0.2\" or \"name set Items Sheet2!A1:A9\"");
+    println!("\tname delete name\tdeletes the name");
+    println!("\tundo\ttakes back the last change, like a set, clear, copy or load");
+    println!("\tredo\tmakes the last undone change again");
+}
+
+// The sheets' names come first, with the current one in brackets.
+fn show_workbook(book: &engine::Workbook) {
+    let names: Vec<String> = book
+        .sheets()
+        .iter()
+        .map(|(name, _)| match *name == book.sheet_name() {
+            true => format!("[{}]", name),
+            false => name.to_string(),
+        })
+        .collect();
+    println!("{}", names.join(" "));
+    show_spreadsheet(book.sheet());
 }
 
+// How much of a sheet is shown, from A1, so that a far away cell doesn't make
+// the grid too big to print.
+const SHOWN_COLS: usize = 16;
+const SHOWN_ROWS: usize = 50;
+
 // TODO: Align columns for longer cell values.
 fn show_spreadsheet(sheet: &engine::Spreadsheet) {
-    let (w, h) = sheet.get_max_dims();
+    let (max_x, max_y) = sheet.get_max_dims();
+    let (w, h) = (max_x.min(SHOWN_COLS - 1), max_y.min(SHOWN_ROWS - 1));
+    for x in 0..w + 1 {
+        print!("\t{}", engine::column_name(x));
+        if x < w {
+            print!(",");
+        }
+    }
+    println!();
     for y in 0..h + 1 {
+        print!("{}\t", y + 1);
         for x in 0..w + 1 {
             let s = sheet.show_cell(x, y);
-            print!("{}", if s == "" { "_" } else { &s });
+            print!("{}", if s.is_empty() { "_" } else { &s });
             if x < w {
                 print!(",\t");
             }
         }
         println!();
     }
+    if (w, h) != (max_x, max_y) {
+        println!(
+            "(showing A1:{} of A1:{}; use \"info cell\" for the rest)",
+            engine::a1_name(w, h),
+            engine::a1_name(max_x, max_y)
+        );
+    }
 }